@@ -0,0 +1,57 @@
+use api::pattern::{bingos, WinPatternKind};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+fn bench_bingos(c: &mut Criterion) {
+    let patterns = WinPatternKind::default_set();
+
+    for (width, height) in [(3, 3), (5, 5), (9, 9), (5, 3)] {
+        let fields = (0..width * height)
+            .map(|i| i % 3 != 0)
+            .collect::<Vec<bool>>();
+
+        c.bench_function(&format!("bingos {width}x{height}"), |b| {
+            b.iter(|| bingos(black_box(&fields), width, height, &patterns))
+        });
+    }
+
+    // every pattern a host can pick, including a custom shape
+
+    let fields = (0..25).map(|i| i % 4 != 0).collect::<Vec<bool>>();
+    let all_patterns = vec![
+        WinPatternKind::Lines,
+        WinPatternKind::Diagonals,
+        WinPatternKind::FourCorners,
+        WinPatternKind::X,
+        WinPatternKind::Plus,
+        WinPatternKind::Blackout,
+        WinPatternKind::Custom {
+            mask: (0..25).map(|i| i % 6 == 0).collect(),
+        },
+    ];
+
+    c.bench_function("bingos 5x5 all patterns", |b| {
+        b.iter(|| bingos(black_box(&fields), 5, 5, &all_patterns))
+    });
+
+    // a full room of players, as scored on every websocket refresh
+
+    let cards = (0..50)
+        .map(|player| {
+            (0..25)
+                .map(|i| (i + player) % 4 != 0)
+                .collect::<Vec<bool>>()
+        })
+        .collect::<Vec<Vec<bool>>>();
+
+    c.bench_function("bingos 50 players 5x5", |b| {
+        b.iter(|| {
+            cards
+                .iter()
+                .map(|fields| bingos(black_box(fields), 5, 5, &patterns))
+                .sum::<i32>()
+        })
+    });
+}
+
+criterion_group!(benches, bench_bingos);
+criterion_main!(benches);
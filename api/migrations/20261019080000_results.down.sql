@@ -0,0 +1,10 @@
+drop trigger games_update on bingo.games;
+drop function game_row_update_notification();
+
+drop table bingo.results;
+
+alter table
+    bingo.players drop column joined_at;
+
+alter table
+    bingo.games drop column closed_at;
@@ -0,0 +1,54 @@
+alter table
+    bingo.games
+add
+    column closed_at timestamptz;
+
+alter table
+    bingo.players
+add
+    column joined_at timestamptz not null default now();
+
+create table bingo.results (
+    game_id uuid not null,
+    "user_id" uuid not null,
+    "username" text not null,
+    bingos integer not null default 0,
+    hits integer not null default 0,
+    joined_at timestamptz not null,
+    left_at timestamptz not null default now(),
+    primary key (game_id, "user_id")
+);
+
+alter table
+    bingo.results
+add
+    constraint results_game_id_fkey foreign key (game_id) references bingo.games (id),
+add
+    constraint "results_user_id_fkey" foreign key ("user_id") references "identity".users (id);
+
+-- notify sockets when a game is closed
+
+create or replace function game_row_update_notification ()
+ returns trigger
+ language plpgsql
+as $$
+declare
+  channel text := tg_argv[0];
+begin
+  perform (
+     with payload(game_id) as
+     (
+       select new.id
+     )
+     select pg_notify(channel, row_to_json(payload)::text)
+       from payload
+  );
+  return null;
+end;
+$$;
+
+create trigger games_update
+         after update
+            on bingo.games
+      for each row
+       execute procedure game_row_update_notification('games_update');
@@ -0,0 +1,2 @@
+alter table
+    bingo.fields drop column version;
@@ -0,0 +1,4 @@
+alter table
+    bingo.fields
+add
+    column version integer not null default 0;
@@ -0,0 +1,16 @@
+delete from
+    bingo.fields
+where
+    free = true;
+
+alter table
+    bingo.fields drop constraint fields_free_without_template,
+    drop column free,
+alter column
+    field_template_id
+set
+    not null;
+
+alter table
+    bingo.games drop column free_cell_caption,
+    drop column free_cell;
@@ -0,0 +1,18 @@
+alter table
+    bingo.games
+add
+    column free_cell boolean not null default false,
+add
+    column free_cell_caption text not null default 'Free' check (
+        length(trim(free_cell_caption)) > 0
+        and length(trim(free_cell_caption)) <= 128
+    );
+
+alter table
+    bingo.fields
+alter column
+    field_template_id drop not null,
+add
+    column free boolean not null default false,
+add
+    constraint fields_free_without_template check (free = (field_template_id is null));
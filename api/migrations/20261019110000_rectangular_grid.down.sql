@@ -0,0 +1,13 @@
+alter table
+    bingo.games
+add
+    column grid_size integer not null default 25 check (grid_size >= 2);
+
+update
+    bingo.games
+set
+    grid_size = greatest(grid_width, grid_height);
+
+alter table
+    bingo.games drop column grid_width,
+    drop column grid_height;
@@ -0,0 +1,29 @@
+alter table
+    bingo.games
+add
+    column grid_width integer,
+add
+    column grid_height integer;
+
+-- games created before grid sizes were selectable stored the field amount (25) instead of the grid size
+update
+    bingo.games
+set
+    grid_width = case when grid_size = 25 then 5 else grid_size end,
+    grid_height = case when grid_size = 25 then 5 else grid_size end;
+
+alter table
+    bingo.games
+alter column
+    grid_width
+set
+    not null,
+alter column
+    grid_height
+set
+    not null,
+add
+    constraint games_grid_width_check check (grid_width >= 2 and grid_width <= 9),
+add
+    constraint games_grid_height_check check (grid_height >= 2 and grid_height <= 9),
+    drop column grid_size;
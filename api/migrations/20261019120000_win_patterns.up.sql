@@ -0,0 +1,4 @@
+alter table
+    bingo.games
+add
+    column win_patterns jsonb not null default '[{"type": "lines"}, {"type": "diagonals"}]';
@@ -0,0 +1,8 @@
+alter table
+    bingo.games
+add
+    column card_mode text not null default 'individual' check (
+        card_mode = 'individual'
+        or card_mode = 'same_card'
+        or card_mode = 'same_captions'
+    );
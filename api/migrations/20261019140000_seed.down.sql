@@ -0,0 +1,2 @@
+alter table
+    bingo.games drop column seed;
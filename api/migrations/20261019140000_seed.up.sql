@@ -0,0 +1,4 @@
+alter table
+    bingo.games
+add
+    column seed bigint not null default ('x' || substr(md5(random() :: text), 1, 16)) :: bit(64) :: bigint;
@@ -0,0 +1,6 @@
+alter table
+    bingo.game_templates drop column rules;
+
+alter table
+    bingo.field_templates drop column category,
+    drop column weight;
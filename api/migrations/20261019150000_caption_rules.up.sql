@@ -0,0 +1,17 @@
+alter table
+    bingo.field_templates
+add
+    column weight integer not null default 1 check (
+        weight >= 1
+        and weight <= 100
+    ),
+add
+    column category text check (
+        length(trim(category)) > 0
+        and length(trim(category)) <= 32
+    );
+
+alter table
+    bingo.game_templates
+add
+    column rules jsonb not null default '[]';
@@ -0,0 +1,23 @@
+alter table
+    bingo.games drop column excluded_captions,
+    drop column pinned_captions;
+
+delete from
+    bingo.fields
+where
+    field_template_id in (
+        select
+            id
+        from
+            bingo.field_templates
+        where
+            game_id is not null
+    );
+
+delete from
+    bingo.field_templates
+where
+    game_id is not null;
+
+alter table
+    bingo.field_templates drop column game_id;
@@ -0,0 +1,17 @@
+-- ad-hoc captions only belong to the game they were added to
+alter table
+    bingo.field_templates
+add
+    column game_id uuid;
+
+alter table
+    bingo.field_templates
+add
+    constraint field_templates_game_id_fkey foreign key (game_id) references bingo.games (id);
+
+alter table
+    bingo.games
+add
+    column pinned_captions uuid [] not null default '{}',
+add
+    column excluded_captions uuid [] not null default '{}';
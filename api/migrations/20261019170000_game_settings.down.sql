@@ -0,0 +1,4 @@
+alter table
+    bingo.games drop column ends_at,
+    drop column max_players,
+    drop column locked;
@@ -0,0 +1,8 @@
+alter table
+    bingo.games
+add
+    column locked boolean not null default false,
+add
+    column max_players integer check (max_players > 0),
+add
+    column ends_at timestamptz;
@@ -0,0 +1,7 @@
+alter table
+    bingo.games drop column round,
+    drop column room_id;
+
+drop table bingo.room_members;
+
+drop table bingo.rooms;
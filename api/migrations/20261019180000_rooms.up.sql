@@ -0,0 +1,50 @@
+-- rooms keep an access code and a roster across successive games
+create table bingo.rooms (
+    id uuid not null primary key default uuid_generate_v4(),
+    title text not null check (
+        length(trim(title)) > 0
+        and length(trim(title)) <= 128
+    ),
+    access_code text not null check (
+        length(trim(access_code)) > 0
+        and length(trim(access_code)) < 64
+    ),
+    created_by uuid not null,
+    created_at timestamptz not null default now()
+);
+
+alter table
+    bingo.rooms
+add
+    constraint rooms_unique_access_code unique (access_code),
+add
+    constraint rooms_created_by_fkey foreign key (created_by) references "identity".users (id);
+
+create table bingo.room_members (
+    room_id uuid not null,
+    "user_id" uuid not null,
+    "username" text not null default 'Anonymous player',
+    joined_at timestamptz not null default now(),
+    primary key (room_id, "user_id")
+);
+
+alter table
+    bingo.room_members
+add
+    constraint room_members_room_id_fkey foreign key (room_id) references bingo.rooms (id),
+add
+    constraint "room_members_user_id_fkey" foreign key ("user_id") references "identity".users (id);
+
+alter table
+    bingo.games
+add
+    column room_id uuid,
+add
+    column round integer check (round > 0);
+
+alter table
+    bingo.games
+add
+    constraint games_room_id_fkey foreign key (room_id) references bingo.rooms (id),
+add
+    constraint games_unique_room_round unique (room_id, round);
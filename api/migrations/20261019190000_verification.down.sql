@@ -0,0 +1,9 @@
+drop trigger confirmations_insert on bingo.confirmations;
+
+drop table bingo.confirmations;
+
+alter table
+    bingo.fields drop column confirmed;
+
+alter table
+    bingo.games drop column required_confirmations;
@@ -0,0 +1,32 @@
+-- checks in verified games only count once enough other players (or the host) confirmed them
+alter table
+    bingo.games
+add
+    column required_confirmations integer check (required_confirmations > 0);
+
+alter table
+    bingo.fields
+add
+    column confirmed boolean not null default false;
+
+create table bingo.confirmations (
+    field_id uuid not null,
+    "user_id" uuid not null,
+    game_id uuid not null,
+    created_at timestamptz not null default now(),
+    primary key (field_id, "user_id")
+);
+
+alter table
+    bingo.confirmations
+add
+    constraint confirmations_field_id_fkey foreign key (field_id) references bingo.fields (id) on delete cascade,
+add
+    constraint "confirmations_user_id_fkey" foreign key ("user_id") references "identity".users (id),
+add
+    constraint confirmations_game_id_fkey foreign key (game_id) references bingo.games (id);
+
+create trigger confirmations_insert
+after
+insert
+    on bingo.confirmations for each row execute procedure game_update_notification('fields_update');
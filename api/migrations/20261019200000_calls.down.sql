@@ -0,0 +1,6 @@
+drop trigger calls_insert on bingo.calls;
+
+drop table bingo.calls;
+
+alter table
+    bingo.games drop column announcers;
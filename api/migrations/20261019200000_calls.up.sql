@@ -0,0 +1,33 @@
+-- announced captions are checked on every card that has them
+alter table
+    bingo.games
+add
+    column announcers text not null default 'nobody' check (
+        announcers = 'nobody'
+        or announcers = 'host'
+        or announcers = 'everyone'
+    );
+
+create table bingo.calls (
+    id uuid not null primary key default uuid_generate_v4(),
+    game_id uuid not null,
+    field_template_id uuid not null,
+    "user_id" uuid,
+    created_at timestamptz not null default now()
+);
+
+alter table
+    bingo.calls
+add
+    constraint calls_unique_caption unique (game_id, field_template_id),
+add
+    constraint calls_game_id_fkey foreign key (game_id) references bingo.games (id),
+add
+    constraint calls_field_template_id_fkey foreign key (field_template_id) references bingo.field_templates (id),
+add
+    constraint "calls_user_id_fkey" foreign key ("user_id") references "identity".users (id);
+
+create trigger calls_insert
+after
+insert
+    on bingo.calls for each row execute procedure game_update_notification('fields_update');
@@ -0,0 +1,6 @@
+alter table
+    bingo.calls drop column drawn;
+
+alter table
+    bingo.games drop column draw_interval_seconds,
+    drop column caller;
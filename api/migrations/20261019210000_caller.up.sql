@@ -0,0 +1,12 @@
+-- in caller games the server draws captions and only drawn captions can be checked
+alter table
+    bingo.games
+add
+    column caller boolean not null default false,
+add
+    column draw_interval_seconds integer check (draw_interval_seconds > 0);
+
+alter table
+    bingo.calls
+add
+    column drawn boolean not null default false;
@@ -0,0 +1,4 @@
+alter table
+    bingo.players drop column team_id;
+
+drop table bingo.teams;
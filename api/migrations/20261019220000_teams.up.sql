@@ -0,0 +1,27 @@
+-- players of a game can play in teams, which are scored together
+create table bingo.teams (
+    id uuid not null primary key default uuid_generate_v4(),
+    game_id uuid not null,
+    name text not null check (
+        length(trim(name)) > 0
+        and length(trim(name)) <= 32
+    ),
+    position smallint not null
+);
+
+alter table
+    bingo.teams
+add
+    constraint teams_unique_name unique (game_id, name),
+add
+    constraint teams_game_id_fkey foreign key (game_id) references bingo.games (id);
+
+alter table
+    bingo.players
+add
+    column team_id uuid;
+
+alter table
+    bingo.players
+add
+    constraint players_team_id_fkey foreign key (team_id) references bingo.teams (id);
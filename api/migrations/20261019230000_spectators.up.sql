@@ -0,0 +1,14 @@
+-- spectators follow a game without a card
+create table bingo.spectators (
+    game_id uuid not null,
+    "user_id" uuid not null,
+    joined_at timestamptz not null default now(),
+    primary key (game_id, "user_id")
+);
+
+alter table
+    bingo.spectators
+add
+    constraint spectators_game_id_fkey foreign key (game_id) references bingo.games (id),
+add
+    constraint "spectators_user_id_fkey" foreign key ("user_id") references "identity".users (id);
@@ -0,0 +1,9 @@
+-- how much of the other players' boards is visible
+alter table
+    bingo.games
+add
+    column board_visibility text not null default 'hits' check (
+        board_visibility = 'hidden'
+        or board_visibility = 'hits'
+        or board_visibility = 'full'
+    );
@@ -0,0 +1,12 @@
+drop trigger messages_update on bingo.messages;
+
+drop trigger messages_insert on bingo.messages;
+
+drop table bingo.reactions;
+
+drop trigger messages_revision on bingo.messages;
+drop function message_revision();
+
+drop table bingo.messages;
+
+drop sequence bingo.message_revisions;
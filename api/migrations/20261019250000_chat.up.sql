@@ -0,0 +1,72 @@
+-- chat messages of a game and emoji reactions to them
+create table bingo.messages (
+    id uuid not null primary key default uuid_generate_v4(),
+    game_id uuid not null,
+    "user_id" uuid not null,
+    "username" text not null,
+    content text not null check (
+        length(trim(content)) > 0
+        and length(content) <= 500
+    ),
+    created_at timestamptz not null default now(),
+    updated_at timestamptz not null default now(),
+    deleted_at timestamptz,
+    revision bigint not null
+);
+
+alter table
+    bingo.messages
+add
+    constraint messages_game_id_fkey foreign key (game_id) references bingo.games (id),
+add
+    constraint "messages_user_id_fkey" foreign key ("user_id") references "identity".users (id);
+
+create index messages_game_id_revision on bingo.messages (game_id, revision);
+
+-- messages get a new revision whenever they change, sockets send everything after the
+-- latest revision they know. Revisions of a game are handed out one transaction at a time,
+-- so a revision is never committed after a higher one of the same game.
+create sequence bingo.message_revisions;
+
+create or replace function message_revision ()
+ returns trigger
+ language plpgsql
+as $$
+begin
+  perform pg_advisory_xact_lock(hashtextextended(new.game_id::text, 0));
+  new.revision := nextval('bingo.message_revisions');
+  return new;
+end;
+$$;
+
+create trigger messages_revision
+before
+insert
+    or
+update
+    on bingo.messages for each row execute procedure message_revision();
+
+create table bingo.reactions (
+    message_id uuid not null,
+    "user_id" uuid not null,
+    emoji text not null,
+    created_at timestamptz not null default now(),
+    primary key (message_id, "user_id", emoji)
+);
+
+alter table
+    bingo.reactions
+add
+    constraint reactions_message_id_fkey foreign key (message_id) references bingo.messages (id) on delete cascade,
+add
+    constraint "reactions_user_id_fkey" foreign key ("user_id") references "identity".users (id);
+
+create trigger messages_insert
+after
+insert
+    on bingo.messages for each row execute procedure game_update_notification('messages_update');
+
+create trigger messages_update
+after
+update
+    on bingo.messages for each row execute procedure game_update_notification('messages_update');
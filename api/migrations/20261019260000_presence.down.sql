@@ -0,0 +1,8 @@
+alter table
+    bingo.players drop column last_seen_at;
+
+drop trigger connections_delete on bingo.connections;
+
+drop trigger connections_insert on bingo.connections;
+
+drop table bingo.connections;
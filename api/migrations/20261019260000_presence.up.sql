@@ -0,0 +1,30 @@
+-- open sockets of players, kept alive by a heartbeat. Sockets that are never closed properly,
+-- e.g. of a crashed server, expire instead of keeping their players online.
+create table bingo.connections (
+    id uuid not null primary key,
+    game_id uuid not null,
+    "user_id" uuid not null,
+    seen_at timestamptz not null default now()
+);
+
+alter table
+    bingo.connections
+add
+    constraint connections_player_fkey foreign key ("user_id", game_id) references bingo.players ("user_id", game_id) on delete cascade;
+
+create index connections_game_id_user_id on bingo.connections (game_id, "user_id");
+
+create trigger connections_insert
+after
+insert
+    on bingo.connections for each row execute procedure game_update_notification('players_update');
+
+create trigger connections_delete
+after
+    delete on bingo.connections for each row execute procedure game_update_notification('players_update');
+
+-- when players were online the last time
+alter table
+    bingo.players
+add
+    column last_seen_at timestamptz;
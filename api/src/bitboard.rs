@@ -0,0 +1,100 @@
+//! Bitboards for scoring bingo cards. Field `i` of a card, counted row by row, is bit `i`.
+
+use std::sync::OnceLock;
+
+pub const MIN_SIZE: usize = 2;
+pub const MAX_SIZE: usize = 9;
+
+/// Masks of all lines and shapes of one grid size.
+#[derive(Debug)]
+pub struct Masks {
+    pub width: usize,
+    pub height: usize,
+    pub rows: Vec<u128>,
+    pub columns: Vec<u128>,
+    /// Only square boards have diagonals.
+    pub diagonals: Vec<u128>,
+    pub corners: u128,
+    /// Middle row and middle column, only on boards with an odd width and height.
+    pub plus: Option<u128>,
+    pub all: u128,
+}
+
+impl Masks {
+    fn new(width: usize, height: usize) -> Masks {
+        let bit = |x: usize, y: usize| 1u128 << (y * width + x);
+
+        let rows = (0..height)
+            .map(|y| (0..width).fold(0, |mask, x| mask | bit(x, y)))
+            .collect::<Vec<u128>>();
+        let columns = (0..width)
+            .map(|x| (0..height).fold(0, |mask, y| mask | bit(x, y)))
+            .collect::<Vec<u128>>();
+
+        let diagonals = if width == height {
+            vec![
+                (0..width).fold(0, |mask, i| mask | bit(i, i)),
+                (0..width).fold(0, |mask, i| mask | bit(i, height - 1 - i)),
+            ]
+        } else {
+            vec![]
+        };
+
+        let plus = if width % 2 == 1 && height % 2 == 1 {
+            Some(rows[height / 2] | columns[width / 2])
+        } else {
+            None
+        };
+
+        Masks {
+            width,
+            height,
+            corners: bit(0, 0)
+                | bit(width - 1, 0)
+                | bit(0, height - 1)
+                | bit(width - 1, height - 1),
+            all: rows.iter().fold(0, |mask, row| mask | row),
+            rows,
+            columns,
+            diagonals,
+            plus,
+        }
+    }
+}
+
+/// Returns the precomputed masks of a grid size, or `None` for unsupported sizes.
+pub fn masks(width: usize, height: usize) -> Option<&'static Masks> {
+    static MASKS: OnceLock<Vec<Masks>> = OnceLock::new();
+
+    let sizes = MIN_SIZE..=MAX_SIZE;
+    if !sizes.contains(&width) || !sizes.contains(&height) {
+        return None;
+    }
+
+    let masks = MASKS.get_or_init(|| {
+        sizes
+            .clone()
+            .flat_map(|height| sizes.clone().map(move |width| Masks::new(width, height)))
+            .collect()
+    });
+
+    masks.get((height - MIN_SIZE) * (MAX_SIZE - MIN_SIZE + 1) + (width - MIN_SIZE))
+}
+
+/// Packs a card's fields into a bitboard. Cards have at most 81 fields.
+pub fn from_fields(fields: &[bool]) -> u128 {
+    fields
+        .iter()
+        .take(128)
+        .enumerate()
+        .fold(0, |bits, (i, hit)| bits | ((*hit as u128) << i))
+}
+
+pub fn covers(bits: u128, mask: u128) -> bool {
+    bits & mask == mask
+}
+
+/// Counts the masks fully covered by `bits`.
+pub fn complete(bits: u128, masks: &[u128]) -> i32 {
+    masks.iter().filter(|mask| covers(bits, **mask)).count() as i32
+}
@@ -1,3 +1,6 @@
+use crate::error::{Error, Result};
+use api::pattern::WinPatternKind;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -17,11 +20,214 @@ pub struct TemplateOut {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TemplateIn {
     pub title: String,
-    pub fields: Vec<String>,
+    pub fields: Vec<FieldTemplateIn>,
+    #[serde(default)]
+    pub rules: Vec<CaptionRule>,
+}
+
+/// A plain caption or a caption with weight and category.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FieldTemplateIn {
+    Caption(String),
+    Detailed {
+        caption: String,
+        weight: Option<i32>,
+        category: Option<String>,
+    },
+}
+
+impl FieldTemplateIn {
+    pub fn caption(&self) -> &str {
+        match self {
+            FieldTemplateIn::Caption(caption) | FieldTemplateIn::Detailed { caption, .. } => {
+                caption
+            }
+        }
+    }
+
+    /// Captions are drawn with odds proportional to their weight, which defaults to one.
+    pub fn weight(&self) -> i32 {
+        match self {
+            FieldTemplateIn::Detailed {
+                weight: Some(weight),
+                ..
+            } => *weight,
+            _ => 1,
+        }
+    }
+
+    pub fn category(&self) -> Option<&str> {
+        match self {
+            FieldTemplateIn::Detailed {
+                category: Some(category),
+                ..
+            } => Some(category.trim()),
+            _ => None,
+        }
+    }
+}
+
+/// Rules a template can set on which captions are drawn onto a card.
+/// Categories refer to the categories of the template's fields.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum CaptionRule {
+    /// At most `max` captions of the category per card.
+    MaxPerCard { category: String, max: usize },
+    /// At least `min` captions of the category per card.
+    MinPerCard { category: String, min: usize },
+    /// One caption of each category in every row.
+    EachPerRow { categories: Vec<String> },
+}
+
+impl CaptionRule {
+    pub fn categories(&self) -> Vec<&str> {
+        match self {
+            CaptionRule::MaxPerCard { category, .. } | CaptionRule::MinPerCard { category, .. } => {
+                vec![category]
+            }
+            CaptionRule::EachPerRow { categories } => {
+                categories.iter().map(|v| v.as_str()).collect()
+            }
+        }
+    }
+
+    pub fn validate(&self, categories: &[&str]) -> Result<()> {
+        let unknown = |category: &str| {
+            Error::BadRequest(format!(
+                "Rules can only refer to categories of the template's fields, not \"{category}\"."
+            ))
+        };
+
+        match self {
+            CaptionRule::MaxPerCard { category, .. } | CaptionRule::MinPerCard { category, .. }
+                if !categories.contains(&category.as_str()) =>
+            {
+                Err(unknown(category))
+            }
+            CaptionRule::MinPerCard { min: 0, .. } => Err(Error::BadRequest(
+                "Minimum rules must require at least one caption.".to_string(),
+            )),
+            CaptionRule::EachPerRow { categories: row } if row.is_empty() => Err(
+                Error::BadRequest("Row rules must contain at least one category.".to_string()),
+            ),
+            CaptionRule::EachPerRow { categories: row } => {
+                for (i, category) in row.iter().enumerate() {
+                    if !categories.contains(&category.as_str()) {
+                        return Err(unknown(category));
+                    }
+                    if row[..i].contains(category) {
+                        return Err(Error::BadRequest(format!(
+                            "Row rules must not contain \"{category}\" more than once."
+                        )));
+                    }
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 // game
 
+/// How cards are dealt to the players of a game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum CardMode {
+    /// Every player gets their own random card.
+    #[default]
+    Individual,
+    /// Everyone gets the same captions in the same positions.
+    SameCard,
+    /// Everyone gets the same captions in different positions.
+    SameCaptions,
+}
+
+/// Who can announce captions, which checks them on every card that has them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum Announcers {
+    #[default]
+    Nobody,
+    Host,
+    /// The host and all players.
+    Everyone,
+}
+
+/// How much players see of the other players' boards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "camelCase")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum BoardVisibility {
+    /// Only bingos and the amount of hits.
+    Hidden,
+    /// Which cells are checked, but not their captions.
+    #[default]
+    Hits,
+    /// Checked cells and captions.
+    Full,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GameOptionsIn {
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    pub free_cell: bool,
+    pub free_cell_caption: Option<String>,
+    pub win_patterns: Option<Vec<WinPatternKind>>,
+    pub card_mode: CardMode,
+    /// Field templates that are on every card.
+    pub pinned_captions: Vec<Uuid>,
+    /// Field templates that are never dealt.
+    pub excluded_captions: Vec<Uuid>,
+    /// Captions that only exist in this game. They are on every card, like pinned ones.
+    pub extra_captions: Vec<String>,
+}
+
+/// Settings of a new game.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameSettingsIn {
+    pub template_id: Uuid,
+    /// Square grid size, unless width and height are given. Defaults to 5.
+    #[serde(default)]
+    pub grid_size: Option<i64>,
+    #[serde(flatten)]
+    pub options: GameOptionsIn,
+    /// No one can join a locked game.
+    #[serde(default)]
+    pub locked: bool,
+    /// Including the host.
+    #[serde(default)]
+    pub max_players: Option<i64>,
+    #[serde(default)]
+    pub time_limit_seconds: Option<i64>,
+    /// Alternative to a time limit.
+    #[serde(default)]
+    pub ends_at: Option<DateTime<Utc>>,
+    /// Checks only count once this many other players confirmed them, or the host did.
+    #[serde(default)]
+    pub required_confirmations: Option<i64>,
+    #[serde(default)]
+    pub announcers: Announcers,
+    /// The server draws captions and only drawn captions can be checked.
+    #[serde(default)]
+    pub caller: bool,
+    /// Draws a caption periodically, otherwise the host asks for each draw.
+    #[serde(default)]
+    pub draw_interval_seconds: Option<i64>,
+    /// Team names. Players are assigned to the smallest team when they join.
+    #[serde(default)]
+    pub teams: Vec<String>,
+    #[serde(default)]
+    pub board_visibility: BoardVisibility,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all(serialize = "camelCase"))]
 pub struct GameOut {
@@ -32,16 +238,142 @@ pub struct GameOut {
     pub fields: Vec<Vec<FieldOut>>,
     pub players: Vec<PlayerOut>,
     pub username: String,
+    pub win_patterns: Vec<WinPatternKind>,
+    pub ends_at: Option<DateTime<Utc>>,
+    pub required_confirmations: Option<i32>,
+    pub announcers: Announcers,
+    pub caller: bool,
+    pub teams: Vec<TeamStandingOut>,
+    pub board_visibility: BoardVisibility,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct JoinIn {
+    /// Team to join instead of the smallest one.
+    pub team_id: Option<Uuid>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SpectateIn {
+    /// Also receive the boards of all players, if the game shows full boards.
+    pub boards: bool,
+}
+
+/// A game as seen by spectators, who have no card of their own.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct SpectatorGameOut {
+    pub id: Uuid,
+    pub open: bool,
+    pub access_code: String,
+    pub players: Vec<PlayerOut>,
+    pub teams: Vec<TeamStandingOut>,
+    pub boards: Vec<BoardOut>,
+    pub win_patterns: Vec<WinPatternKind>,
+    pub ends_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct BoardOut {
+    pub user_id: Uuid,
+    pub username: String,
+    pub fields: Vec<Vec<FieldOut>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
+pub struct ActiveGameOut {
+    pub id: Uuid,
+    pub title: String,
+    pub access_code: String,
+    pub is_host: bool,
+    pub player_amount: i64,
+    pub room_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct FieldOut {
     pub id: Uuid,
+    /// The caption's field template, none for free cells.
+    pub field_template_id: Option<Uuid>,
     pub text: String,
     pub position: u32,
     pub checked: bool,
     pub bingo: bool,
+    pub free: bool,
+    pub version: i32,
+    /// Checked, but not confirmed yet.
+    pub provisional: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldIn {
+    pub checked: bool,
+    pub version: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldUpdateIn {
+    pub id: Uuid,
+    pub checked: bool,
+    pub version: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldsIn {
+    pub fields: Vec<FieldUpdateIn>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldStateOut {
+    pub id: Uuid,
+    pub checked: bool,
+    pub version: i32,
+}
+
+/// Grid size for re-dealt cards, defaults to the game's current one.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DealIn {
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallIn {
+    pub field_template_id: Uuid,
+}
+
+/// An announced caption.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallOut {
+    pub field_template_id: Uuid,
+    pub caption: String,
+    pub user_id: Option<Uuid>,
+    pub username: Option<String>,
+    pub called_at: DateTime<Utc>,
+    /// Drawn by the caller instead of announced.
+    pub drawn: bool,
+}
+
+/// Result of comparing a player's card with the one derived from the game's seed.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CardCheckOut {
+    pub user_id: Uuid,
+    /// As string, since JavaScript numbers can not hold every 64 bit integer.
+    pub seed: String,
+    pub valid: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -50,7 +382,42 @@ pub struct PlayerOut {
     pub user_id: Uuid,
     pub username: String,
     pub bingos: i32,
+    /// Checked cells row by row, empty if the game hides other boards.
     pub hits: Vec<bool>,
+    pub hit_amount: i32,
+    /// Captions row by row, only if the game shows full boards.
+    pub captions: Vec<String>,
+    pub is_me: bool,
+    pub team_id: Option<Uuid>,
+    /// Has the game open in at least one socket.
+    pub online: bool,
+    pub last_seen_at: Option<DateTime<Utc>>,
+}
+
+/// Bingos and hits of all players of a team.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct TeamStandingOut {
+    pub id: Uuid,
+    pub name: String,
+    pub player_amount: i64,
+    pub bingos: i32,
+    pub hits: i32,
+    pub rank: u32,
+    pub is_mine: bool,
+}
+
+/// A check of a verified game that waits for confirmations.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct PendingCheckOut {
+    pub field_id: Uuid,
+    pub user_id: Uuid,
+    pub username: String,
+    pub caption: String,
+    pub confirmations: i64,
+    pub required_confirmations: i32,
+    pub confirmed_by_me: bool,
     pub is_me: bool,
 }
 
@@ -67,6 +434,30 @@ pub enum MessageOut {
     Fields(Vec<Vec<FieldOut>>),
     #[serde(rename_all(serialize = "camelCase"))]
     Players(Vec<PlayerOut>),
+    #[serde(rename_all(serialize = "camelCase"))]
+    Teams(Vec<TeamStandingOut>),
+    /// Boards of all players, for spectators who asked for them.
+    #[serde(rename_all(serialize = "camelCase"))]
+    Boards(Vec<BoardOut>),
+    #[serde(rename_all(serialize = "camelCase"))]
+    PendingChecks(Vec<PendingCheckOut>),
+    /// Captions announced or drawn so far, the latest first.
+    #[serde(rename_all(serialize = "camelCase"))]
+    Calls(Vec<CallOut>),
+    /// Time left in games with a deadline.
+    #[serde(rename_all(serialize = "camelCase"))]
+    Countdown {
+        ends_at: DateTime<Utc>,
+        seconds_left: i64,
+    },
+    #[serde(rename_all(serialize = "camelCase"))]
+    Ended { id: Uuid },
+    /// The latest chat messages, sent once the socket is connected.
+    #[serde(rename_all(serialize = "camelCase"))]
+    ChatHistory(Vec<ChatMessageOut>),
+    /// New, deleted or reacted to messages.
+    #[serde(rename_all(serialize = "camelCase"))]
+    Chat(Vec<ChatMessageOut>),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -75,7 +466,176 @@ pub struct UsernameIn {
     pub username: String,
 }
 
+/// The username as stored, which may differ from the requested one.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct UsernameOut {
+    pub username: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatMessageIn {
+    pub content: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionIn {
+    pub emoji: String,
+}
+
+/// A chat message. Deleted messages lose their content and reactions.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ChatMessageOut {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub username: String,
+    pub content: String,
+    pub reactions: Vec<ReactionOut>,
+    pub deleted: bool,
+    pub is_me: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Grows with every change of a message, e.g. a new reaction.
+    pub revision: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ReactionOut {
+    pub emoji: String,
+    pub amount: i64,
+    pub reacted_by_me: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TeamIn {
+    pub team_id: Uuid,
+}
+
+// results
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameResultsOut {
+    pub id: Uuid,
+    pub title: String,
+    pub finished: bool,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub duration_seconds: i64,
+    pub standings: Vec<StandingOut>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StandingOut {
+    pub user_id: Uuid,
+    pub username: String,
+    pub bingos: i32,
+    pub hits: i32,
+    pub rank: u32,
+    pub winner: bool,
+    pub active: bool,
+    pub duration_seconds: i64,
+    pub is_me: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PastGameOut {
+    pub id: Uuid,
+    pub title: String,
+    pub finished: bool,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub player_amount: i64,
+    pub bingos: i32,
+    pub hits: i32,
+    pub rank: i64,
+    pub winner: bool,
+}
+
+// room
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoomIn {
+    pub title: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomOut {
+    pub id: Uuid,
+    pub title: String,
+    pub access_code: String,
+    pub is_host: bool,
+    pub members: Vec<RoomMemberOut>,
+    pub rounds: Vec<RoundOut>,
+    /// The round that is still running, if any.
+    pub game_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomMemberOut {
+    pub user_id: Uuid,
+    pub username: String,
+    pub is_me: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoundOut {
+    pub game_id: Uuid,
+    pub round: i32,
+    pub title: String,
+    pub finished: bool,
+}
+
+/// Standing of a player summed up over all rounds of a room.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScoreOut {
+    pub user_id: Uuid,
+    pub username: String,
+    pub rounds: i32,
+    pub wins: i32,
+    pub bingos: i32,
+    pub hits: i32,
+    pub rank: u32,
+    pub is_me: bool,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct PgGameUpdateNotification {
     pub game_id: Uuid,
 }
+
+#[test]
+fn test_validate_caption_rule() {
+    let categories = ["common", "rare"];
+    let max = |category: &str, max| CaptionRule::MaxPerCard {
+        category: category.to_string(),
+        max,
+    };
+    let row = |categories: &[&str]| CaptionRule::EachPerRow {
+        categories: categories.iter().map(|v| v.to_string()).collect(),
+    };
+
+    assert!(max("rare", 2).validate(&categories).is_ok());
+    assert!(max("epic", 2).validate(&categories).is_err());
+    assert!(CaptionRule::MinPerCard {
+        category: "rare".to_string(),
+        min: 0
+    }
+    .validate(&categories)
+    .is_err());
+    assert!(row(&["common", "rare"]).validate(&categories).is_ok());
+    assert!(row(&[]).validate(&categories).is_err());
+    assert!(row(&["rare", "rare"]).validate(&categories).is_err());
+    assert!(row(&["rare", "epic"]).validate(&categories).is_err());
+}
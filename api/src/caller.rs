@@ -0,0 +1,89 @@
+use crate::{error::Result, handler::game::call::draw_caption};
+use sqlx::postgres::PgPool;
+use tokio::time::{interval, Duration};
+use uuid::Uuid;
+
+/// Draws captions in caller games with a draw interval.
+pub async fn draw_captions(pool: &PgPool) {
+    let mut interval = interval(Duration::from_secs(1));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = draw(pool).await {
+            tracing::error!("drawing captions failes, err: {:?}", err);
+        }
+    }
+}
+
+/// Each game draws in its own transaction, so one failing game does not hold up the others.
+async fn draw(pool: &PgPool) -> Result<()> {
+    let games = sqlx::query!(
+        r#"
+            select g.id from bingo.games g
+            where
+                g.closed = false
+                and g.caller
+                and g.draw_interval_seconds is not null
+                and exists (
+                    select from bingo.field_templates ft
+                    where
+                        ft.game_template_id = g.game_template_id
+                        and (ft.game_id is null or ft.game_id = g.id)
+                        and ft.id <> all(g.excluded_captions)
+                        and not exists (
+                            select from bingo.calls c
+                            where c.game_id = g.id and c.field_template_id = ft.id
+                        )
+                )
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for game in games {
+        if let Err(err) = draw_due_caption(game.id, pool).await {
+            tracing::error!(
+                "drawing a caption of game {} failes, err: {:?}",
+                game.id,
+                err
+            );
+        }
+    }
+
+    Ok(())
+}
+
+async fn draw_due_caption(game_id: Uuid, pool: &PgPool) -> Result<()> {
+    let mut transaction = pool.begin().await?;
+
+    // the interval starts over with draws requested by the host
+
+    let game = sqlx::query!(
+        r#"
+            select g.id from bingo.games g
+            where
+                g.id = $1
+                and g.closed = false
+                and coalesce(g.ends_at > now(), true)
+                and coalesce(
+                    (select max(c.created_at) from bingo.calls c where c.game_id = g.id and c.drawn),
+                    g.created_at
+                ) + make_interval(secs => g.draw_interval_seconds) <= now()
+            for update skip locked
+        "#,
+        game_id
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+
+    if let Some(game) = game {
+        if draw_caption(game.id, &mut transaction).await?.is_none() {
+            tracing::debug!("all captions of game {} have been drawn", game.id);
+        }
+    }
+
+    transaction.commit().await?;
+
+    Ok(())
+}
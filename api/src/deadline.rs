@@ -0,0 +1,59 @@
+use crate::{error::Result, handler::game::result::close_game};
+use sqlx::postgres::PgPool;
+use tokio::time::{interval, Duration};
+use uuid::Uuid;
+
+/// Closes games once their deadline has passed.
+pub async fn close_expired_games(pool: &PgPool) {
+    let mut interval = interval(Duration::from_secs(1));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = close_games(pool).await {
+            tracing::error!("closing expired games failes, err: {:?}", err);
+        }
+    }
+}
+
+/// Each game is closed in its own transaction, so one failing game does not keep the others open.
+async fn close_games(pool: &PgPool) -> Result<()> {
+    let games =
+        sqlx::query!("select id from bingo.games where closed = false and ends_at <= now()")
+            .fetch_all(pool)
+            .await?;
+
+    for game in games {
+        if let Err(err) = close_expired_game(game.id, pool).await {
+            tracing::error!("closing expired game {} failes, err: {:?}", game.id, err);
+        }
+    }
+
+    Ok(())
+}
+
+async fn close_expired_game(game_id: Uuid, pool: &PgPool) -> Result<()> {
+    let mut transaction = pool.begin().await?;
+
+    // skip games that are being closed by their host right now
+
+    let game = sqlx::query!(
+        r#"
+            select id from bingo.games
+            where id = $1 and closed = false and ends_at <= now()
+            for update skip locked
+        "#,
+        game_id
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+
+    if let Some(game) = game {
+        close_game(game.id, &mut transaction).await?;
+        tracing::info!("closed expired game {}", game.id);
+    }
+
+    transaction.commit().await?;
+
+    Ok(())
+}
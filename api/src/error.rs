@@ -10,6 +10,8 @@ pub enum Error {
     InvalidCredentials,
     BadRequest(String),
     NotFound,
+    Conflict(String),
+    TooManyRequests(String),
     InternalServer,
 }
 
@@ -21,6 +23,8 @@ impl IntoResponse for Error {
             }
             Error::BadRequest(error) => (StatusCode::BAD_REQUEST, error),
             Error::NotFound => (StatusCode::NOT_FOUND, "Not found".to_string()),
+            Error::Conflict(error) => (StatusCode::CONFLICT, error),
+            Error::TooManyRequests(error) => (StatusCode::TOO_MANY_REQUESTS, error),
             Error::InternalServer => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Internal server error".to_string(),
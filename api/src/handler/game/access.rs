@@ -1,157 +1,564 @@
 use crate::{
-    body::GameOut,
+    body::{
+        ActiveGameOut, Announcers, BoardVisibility, CardMode, GameOptionsIn, GameOut,
+        GameSettingsIn, JoinIn, SpectateIn, SpectatorGameOut,
+    },
     error::{Error, Result},
-    handler::game::{field::create_fields_for_player, player::ger_players},
-    server::{AppState, Identity},
+    handler::game::{
+        field::{create_fields_for_player, list_boards},
+        player::{ger_players, list_teams, set_team},
+        result::archive_players,
+    },
+    server::{AppState, Identity, OptionalJson},
 };
+use api::pattern::WinPatternKind;
 use axum::{
-    extract::{Extension, Path},
+    extract::{Extension, Path, Query},
     Json,
 };
+use chrono::{DateTime, Duration, Utc};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use sqlx::PgConnection;
+use sqlx::{types::Json as PgJson, PgConnection};
 use uuid::Uuid;
 
+/// Starts a new game. Players can take part in several games of the same template at once.
+pub async fn handle_create_game(
+    identity: Identity,
+    Json(payload): Json<GameSettingsIn>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<GameOut>> {
+    let settings = validate_settings(payload)?;
+
+    let mut transaction = state.pool.begin().await?;
+
+    let result = start_game(identity.user_id, settings, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(result)
+}
+
+/// Older variant of `handle_create_game` with the template and grid size in the path.
+/// It continues the caller's running game of the template instead of starting another one.
 pub async fn handle_start_game(
     identity: Identity,
     Path((game_template_id, grid_size)): Path<(Uuid, i64)>,
+    OptionalJson(options): OptionalJson<GameOptionsIn>,
     Extension(state): Extension<AppState>,
 ) -> Result<Json<GameOut>> {
     let mut transaction = state.pool.begin().await?;
     let user_id = identity.user_id;
 
-    if !(2..=8).contains(&grid_size) {
-        return Err(Error::BadRequest(
-            "Grid size must be grater than one and less than 9.".to_string(),
-        ));
-    }
-
     let game = sqlx::query!(
         r#"
             select
-                g.id,
                 g.access_code
             from bingo.games g
             join bingo.players p on p.game_id = g.id
             where 
                 g.game_template_id = $1
                 and p.user_id = $2
+                and g.closed = false
+                and g.room_id is null
+            order by g.created_at desc
+            limit 1
         "#,
         game_template_id,
         user_id,
     )
-    .fetch_optional(&mut *transaction)
+    .fetch_optional(&mut transaction)
     .await?;
 
-    let result = if let Some(game) = game {
-        join_game(user_id, game.access_code, &mut transaction).await
-    } else {
-        let game_template = sqlx::query!(
-            r#"
-                select id from bingo.game_templates
-                where id = $1 and (created_by = $2 or approved = true)
-            "#,
-            game_template_id,
-            user_id
-        )
-        .fetch_one(&mut *transaction)
-        .await?;
-
-        let field_amount = sqlx::query!(
-            "select count(id) amount from bingo.field_templates where game_template_id = $1",
-            game_template.id
-        )
-        .fetch_one(&mut *transaction)
-        .await?
-        .amount
-        .unwrap_or(0);
-
-        if field_amount < grid_size * grid_size {
+    if let Some(game) = game {
+        let result = join_game(user_id, game.access_code, None, &mut transaction).await?;
+        transaction.commit().await?;
+
+        return Ok(result);
+    }
+
+    let payload = GameSettingsIn {
+        template_id: game_template_id,
+        grid_size: Some(grid_size),
+        options,
+        locked: false,
+        max_players: None,
+        time_limit_seconds: None,
+        ends_at: None,
+        required_confirmations: None,
+        announcers: Announcers::Nobody,
+        caller: false,
+        draw_interval_seconds: None,
+        teams: Vec::new(),
+        board_visibility: BoardVisibility::Hits,
+    };
+
+    // the same transaction, so requests never hold two connections of the pool at once
+
+    let result = start_game(user_id, validate_settings(payload)?, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(result)
+}
+
+/// Game settings that passed validation.
+#[derive(Debug)]
+pub struct GameSettings {
+    template_id: Uuid,
+    width: i64,
+    height: i64,
+    free_cell: bool,
+    free_cell_caption: String,
+    win_patterns: Vec<WinPatternKind>,
+    card_mode: CardMode,
+    pinned_captions: Vec<Uuid>,
+    excluded_captions: Vec<Uuid>,
+    extra_captions: Vec<String>,
+    locked: bool,
+    max_players: Option<i32>,
+    ends_at: Option<DateTime<Utc>>,
+    required_confirmations: Option<i32>,
+    announcers: Announcers,
+    caller: bool,
+    draw_interval_seconds: Option<i32>,
+    teams: Vec<String>,
+    board_visibility: BoardVisibility,
+}
+
+impl GameSettings {
+    fn caption_amount(&self) -> i64 {
+        self.width * self.height - self.free_cell as i64
+    }
+}
+
+/// Checks all settings that do not depend on the template.
+/// Checks the grid size of cards and that it fits the free cell, the win patterns
+/// and the captions pinned to or added to every card.
+pub fn validate_grid(
+    (width, height): (i64, i64),
+    free_cell: bool,
+    win_patterns: &[WinPatternKind],
+    pinned_amount: i64,
+) -> Result<()> {
+    if !(2..=8).contains(&width) || !(2..=8).contains(&height) {
+        return Err(Error::BadRequest(
+            "Grid width and height must be greater than one and less than 9.".to_string(),
+        ));
+    }
+
+    if free_cell && (width % 2 == 0 || height % 2 == 0) {
+        return Err(Error::BadRequest(
+            "A free cell needs an odd grid width and height to have a center.".to_string(),
+        ));
+    }
+
+    for win_pattern in win_patterns {
+        win_pattern.validate(width as usize, height as usize)?;
+    }
+
+    let caption_amount = width * height - free_cell as i64;
+
+    if pinned_amount > caption_amount {
+        return Err(Error::BadRequest(format!(
+            "At most {caption_amount} captions can be pinned or added on a {width}x{height} grid."
+        )));
+    }
+
+    Ok(())
+}
+
+pub fn validate_settings(settings: GameSettingsIn) -> Result<GameSettings> {
+    let options = settings.options;
+    let grid_size = settings.grid_size.unwrap_or(5);
+
+    // the grid size can be overridden to get rectangular boards
+
+    let width = options.width.unwrap_or(grid_size);
+    let height = options.height.unwrap_or(grid_size);
+
+    let win_patterns = options
+        .win_patterns
+        .unwrap_or_else(WinPatternKind::default_set);
+
+    if win_patterns.is_empty() || win_patterns.len() > 16 {
+        return Err(Error::BadRequest(
+            "Games must have at least one and at most 16 win patterns.".to_string(),
+        ));
+    }
+
+    let free_cell_caption = options
+        .free_cell_caption
+        .map(|v| v.trim().to_string())
+        .unwrap_or_else(|| "Free".to_string());
+
+    if free_cell_caption.is_empty() || free_cell_caption.chars().count() > 128 {
+        return Err(Error::BadRequest(
+            "Free cell caption must have at least one and at most 128 characters.".to_string(),
+        ));
+    }
+
+    // captions the host forces onto or removes from the cards
+
+    let mut pinned_captions = options.pinned_captions;
+    pinned_captions.sort();
+    pinned_captions.dedup();
+
+    let mut excluded_captions = options.excluded_captions;
+    excluded_captions.sort();
+    excluded_captions.dedup();
+
+    let mut extra_captions = options
+        .extra_captions
+        .iter()
+        .map(|v| v.trim().to_string())
+        .collect::<Vec<String>>();
+    extra_captions.sort();
+    extra_captions.dedup();
+
+    if extra_captions
+        .iter()
+        .any(|v| v.is_empty() || v.chars().count() > 128)
+    {
+        return Err(Error::BadRequest(
+            "Field captions must have at least one and at most 128 characters.".to_string(),
+        ));
+    }
+
+    if pinned_captions
+        .iter()
+        .any(|v| excluded_captions.contains(v))
+    {
+        return Err(Error::BadRequest(
+            "Captions can not be pinned and excluded at once.".to_string(),
+        ));
+    }
+
+    validate_grid(
+        (width, height),
+        options.free_cell,
+        &win_patterns,
+        (pinned_captions.len() + extra_captions.len()) as i64,
+    )?;
+
+    if let Some(max_players) = settings.max_players {
+        if !(1..=256).contains(&max_players) {
+            return Err(Error::BadRequest(
+                "Games must allow at least one and at most 256 players.".to_string(),
+            ));
+        }
+    }
+
+    if let Some(required_confirmations) = settings.required_confirmations {
+        if !(1..=16).contains(&required_confirmations) {
+            return Err(Error::BadRequest(
+                "Checks must require at least one and at most 16 confirmations.".to_string(),
+            ));
+        }
+    }
+
+    if let Some(draw_interval_seconds) = settings.draw_interval_seconds {
+        if !settings.caller {
+            return Err(Error::BadRequest(
+                "Only caller games can draw captions periodically.".to_string(),
+            ));
+        }
+        if !(5..=3600).contains(&draw_interval_seconds) {
+            return Err(Error::BadRequest(
+                "Captions must be drawn at least five seconds apart and at least once an hour."
+                    .to_string(),
+            ));
+        }
+    }
+
+    let teams = settings
+        .teams
+        .iter()
+        .map(|v| v.trim().to_string())
+        .collect::<Vec<String>>();
+
+    if teams.len() == 1 || teams.len() > 16 {
+        return Err(Error::BadRequest(
+            "Games can have no teams or at least two and at most 16.".to_string(),
+        ));
+    }
+
+    for (i, team) in teams.iter().enumerate() {
+        if team.is_empty() || team.chars().count() > 32 {
+            return Err(Error::BadRequest(
+                "Team names must have at least one and at most 32 characters.".to_string(),
+            ));
+        }
+        if teams[..i].contains(team) {
             return Err(Error::BadRequest(format!(
-                "Game template has not enough fields ({field_amount}) for the selected grid size of {grid_size}."
+                "Team names must be unique, \"{team}\" is used more than once."
             )));
         }
+    }
+
+    // games end after a time limit or at a fixed time
 
-        let game_access_code: String = thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(16)
-            .map(char::from)
-            .collect();
-
-        let game = sqlx::query!(
-            r#"
-                insert into bingo.games (game_template_id, access_code, grid_size, created_by)
-                values ($1, $2, $3, $4)
-                returning *
-            "#,
-            game_template_id,
-            game_access_code,
-            grid_size as i32,
-            user_id
-        )
-        .fetch_one(&mut *transaction)
-        .await?;
-
-        let fields = create_fields_for_player(
-            game.game_template_id,
-            game.id,
-            user_id,
-            grid_size,
-            &mut transaction,
-        )
-        .await?;
-
-        let players = ger_players(game.id, user_id, &mut transaction).await?;
-
-        let username = players
-            .iter()
-            .find(|v| v.is_me)
-            .map(|v| v.username.clone())
-            .unwrap_or_else(|| {
-                tracing::warn!("could not find user in list of players");
-                "unknown".to_string()
-            });
-
-        Ok(Json(GameOut {
-            id: game.id,
-            open: true,
-            continued: false,
-            access_code: game.access_code,
-            fields,
-            players,
-            username,
-        }))
+    let now = Utc::now();
+    let ends_at = match (settings.time_limit_seconds, settings.ends_at) {
+        (Some(_), Some(_)) => {
+            return Err(Error::BadRequest(
+                "Games can either have a time limit or an end, not both.".to_string(),
+            ))
+        }
+        (Some(time_limit_seconds), None) if !(60..=86400).contains(&time_limit_seconds) => {
+            return Err(Error::BadRequest(
+                "Time limits must be at least one minute and at most one day.".to_string(),
+            ))
+        }
+        (Some(time_limit_seconds), None) => Some(now + Duration::seconds(time_limit_seconds)),
+        (None, Some(ends_at))
+            if ends_at < now + Duration::minutes(1) || ends_at > now + Duration::days(1) =>
+        {
+            return Err(Error::BadRequest(
+                "Games must end in at least one minute and at most one day.".to_string(),
+            ))
+        }
+        (None, ends_at) => ends_at,
     };
 
-    transaction.commit().await?;
+    Ok(GameSettings {
+        template_id: settings.template_id,
+        width,
+        height,
+        free_cell: options.free_cell,
+        free_cell_caption,
+        win_patterns,
+        card_mode: options.card_mode,
+        pinned_captions,
+        excluded_captions,
+        extra_captions,
+        locked: settings.locked,
+        max_players: settings.max_players.map(|v| v as i32),
+        ends_at,
+        required_confirmations: settings.required_confirmations.map(|v| v as i32),
+        announcers: settings.announcers,
+        caller: settings.caller,
+        draw_interval_seconds: settings.draw_interval_seconds.map(|v| v as i32),
+        teams,
+        board_visibility: settings.board_visibility,
+    })
+}
 
-    result
+async fn start_game(
+    user_id: Uuid,
+    settings: GameSettings,
+    conn: &mut PgConnection,
+) -> Result<Json<GameOut>> {
+    let game = create_game(user_id, &settings, None, &mut *conn).await?;
+
+    let fields = create_fields_for_player(game.id, user_id, &mut *conn).await?;
+
+    let players = ger_players(game.id, user_id, &mut *conn).await?;
+
+    let teams = list_teams(game.id, &players, &mut *conn).await?;
+
+    let username = players
+        .iter()
+        .find(|v| v.is_me)
+        .map(|v| v.username.clone())
+        .unwrap_or_else(|| {
+            tracing::warn!("could not find user in list of players");
+            "unknown".to_string()
+        });
+
+    Ok(Json(GameOut {
+        id: game.id,
+        open: true,
+        continued: false,
+        access_code: game.access_code,
+        fields,
+        players,
+        username,
+        win_patterns: settings.win_patterns,
+        ends_at: game.ends_at,
+        required_confirmations: settings.required_confirmations,
+        announcers: settings.announcers,
+        caller: settings.caller,
+        teams,
+        board_visibility: settings.board_visibility,
+    }))
 }
 
+pub struct NewGame {
+    pub id: Uuid,
+    pub access_code: String,
+    pub ends_at: Option<DateTime<Utc>>,
+}
+
+/// Checks the settings against the template and inserts the game with its ad-hoc captions.
+/// Cards are dealt separately.
+pub async fn create_game(
+    user_id: Uuid,
+    settings: &GameSettings,
+    room: Option<(Uuid, i32)>,
+    conn: &mut PgConnection,
+) -> Result<NewGame> {
+    let game_template = sqlx::query!(
+        r#"
+            select id from bingo.game_templates
+            where id = $1 and (created_by = $2 or approved = true)
+        "#,
+        settings.template_id,
+        user_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let field_template_ids = sqlx::query!(
+        r#"
+            select id from bingo.field_templates
+            where game_template_id = $1 and game_id is null
+        "#,
+        game_template.id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|v| v.id)
+    .collect::<Vec<Uuid>>();
+
+    if settings
+        .pinned_captions
+        .iter()
+        .chain(&settings.excluded_captions)
+        .any(|v| !field_template_ids.contains(v))
+    {
+        return Err(Error::BadRequest(
+            "Pinned and excluded captions must belong to the game template.".to_string(),
+        ));
+    }
+
+    let field_amount = (field_template_ids.len() - settings.excluded_captions.len()
+        + settings.extra_captions.len()) as i64;
+
+    if field_amount < settings.caption_amount() {
+        return Err(Error::BadRequest(format!(
+            "Game template has not enough fields ({field_amount}) for the selected grid size of {}x{}.",
+            settings.width, settings.height
+        )));
+    }
+
+    let game_access_code: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect();
+
+    let game = sqlx::query!(
+        r#"
+            insert into bingo.games (
+                game_template_id,
+                access_code,
+                grid_width,
+                grid_height,
+                created_by,
+                free_cell,
+                free_cell_caption,
+                win_patterns,
+                card_mode,
+                pinned_captions,
+                excluded_captions,
+                locked,
+                max_players,
+                ends_at,
+                room_id,
+                round,
+                required_confirmations,
+                announcers,
+                caller,
+                draw_interval_seconds,
+                board_visibility
+            )
+            values (
+                $1, $2, $3, $4, $5, $6, $7, $8, $9, $10,
+                $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21
+            )
+            returning id, access_code, ends_at
+        "#,
+        game_template.id,
+        game_access_code,
+        settings.width as i32,
+        settings.height as i32,
+        user_id,
+        settings.free_cell,
+        settings.free_cell_caption,
+        serde_json::to_value(&settings.win_patterns)?,
+        settings.card_mode as CardMode,
+        &settings.pinned_captions,
+        &settings.excluded_captions,
+        settings.locked,
+        settings.max_players,
+        settings.ends_at,
+        room.map(|v| v.0),
+        room.map(|v| v.1),
+        settings.required_confirmations,
+        settings.announcers as Announcers,
+        settings.caller,
+        settings.draw_interval_seconds,
+        settings.board_visibility as BoardVisibility,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            insert into bingo.teams (game_id, name, position)
+            select $1, name, position - 1
+            from unnest($2::text[]) with ordinality as t(name, position)
+        "#,
+        game.id,
+        &settings.teams,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            insert into bingo.field_templates (game_template_id, game_id, caption)
+            select $1, $2, caption from unnest($3::text[]) as caption
+        "#,
+        game_template.id,
+        game.id,
+        &settings.extra_captions,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(NewGame {
+        id: game.id,
+        access_code: game.access_code,
+        ends_at: game.ends_at,
+    })
+}
+
+/// Players can pick a team via `teamId` query parameter, otherwise they join the smallest one.
 pub async fn handle_join_game(
     identity: Identity,
     Path(access_code): Path<String>,
+    Query(options): Query<JoinIn>,
     Extension(state): Extension<AppState>,
 ) -> Result<Json<GameOut>> {
     let mut transaction = state.pool.begin().await?;
     let user_id = identity.user_id;
 
-    let result = join_game(user_id, access_code, &mut transaction).await?;
+    let result = join_game(user_id, access_code, options.team_id, &mut transaction).await?;
 
     transaction.commit().await?;
 
     Ok(result)
 }
 
-pub async fn handle_leave_game(
+/// Leaves every game of a template. Kept for older clients, `handle_leave_game` addresses one game.
+pub async fn handle_leave_template_games(
     identity: Identity,
     Path(game_template_id): Path<Uuid>,
     Extension(state): Extension<AppState>,
 ) -> Result<()> {
-    let pool = state.pool;
-    let user_id = identity.user_id;
-
-    let mut transaction = pool.begin().await?;
+    let mut transaction = state.pool.begin().await?;
 
     let game_ids = sqlx::query!(
         "select id from bingo.games where game_template_id = $1",
@@ -163,6 +570,90 @@ pub async fn handle_leave_game(
     .map(|v| v.id)
     .collect::<Vec<Uuid>>();
 
+    leave_games(&game_ids, identity.user_id, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+pub async fn handle_leave_game(
+    identity: Identity,
+    Path(game_id): Path<Uuid>,
+    Extension(state): Extension<AppState>,
+) -> Result<()> {
+    let mut transaction = state.pool.begin().await?;
+    let user_id = identity.user_id;
+
+    let game = sqlx::query!(
+        r#"
+            select g.id
+            from bingo.games g
+            join bingo.players p on p.game_id = g.id
+            where g.id = $1 and p.user_id = $2
+        "#,
+        game_id,
+        user_id,
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    leave_games(&[game.id], user_id, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Lists the running games the player takes part in.
+pub async fn handle_list_games(
+    identity: Identity,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<Vec<ActiveGameOut>>> {
+    let user_id = identity.user_id;
+
+    let games = sqlx::query!(
+        r#"
+            select
+                g.id,
+                gt.title,
+                g.access_code,
+                g.created_by,
+                g.room_id,
+                (select count(*) from bingo.players ap where ap.game_id = g.id) as "player_amount!"
+            from
+                bingo.games g
+                join bingo.players p on p.game_id = g.id
+                join bingo.game_templates gt on gt.id = g.game_template_id
+            where
+                p.user_id = $1
+                and g.closed = false
+            order by g.created_at desc
+        "#,
+        user_id
+    )
+    .fetch_all(&state.pool)
+    .await?
+    .into_iter()
+    .map(|v| ActiveGameOut {
+        id: v.id,
+        title: v.title,
+        access_code: v.access_code,
+        is_host: v.created_by == user_id,
+        player_amount: v.player_amount,
+        room_id: v.room_id,
+    })
+    .collect::<Vec<ActiveGameOut>>();
+
+    Ok(Json(games))
+}
+
+/// Removes the player's cards from the games, after archiving their standings.
+async fn leave_games(game_ids: &[Uuid], user_id: Uuid, conn: &mut PgConnection) -> Result<()> {
+    for game_id in game_ids {
+        archive_players(*game_id, Some(user_id), &mut *conn).await?;
+    }
+
     sqlx::query!(
         r#"
             delete from 
@@ -171,10 +662,10 @@ pub async fn handle_leave_game(
                 game_id = any($1)
                 and user_id = $2
         "#,
-        &game_ids,
+        game_ids,
         &user_id
     )
-    .execute(&mut transaction)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query!(
@@ -185,53 +676,136 @@ pub async fn handle_leave_game(
                 game_id = any($1)
                 and user_id = $2
         "#,
-        &game_ids,
+        game_ids,
         &user_id
     )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Follows a game without being dealt a card or showing up in the standings.
+/// Spectators do not count towards the player limit and can watch locked games,
+/// but not finished ones.
+pub async fn handle_spectate_game(
+    identity: Identity,
+    Path(access_code): Path<String>,
+    Query(options): Query<SpectateIn>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<SpectatorGameOut>> {
+    let mut transaction = state.pool.begin().await?;
+    let user_id = identity.user_id;
+
+    let game = sqlx::query!(
+        r#"
+            select
+                g.id,
+                g.access_code,
+                g.win_patterns as "win_patterns: PgJson<Vec<WinPatternKind>>",
+                g.ends_at
+            from bingo.games g
+            where g.access_code = $1 and g.closed = false
+        "#,
+        access_code,
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            insert into bingo.spectators (game_id, "user_id") values ($1, $2)
+            on conflict do nothing
+        "#,
+        game.id,
+        user_id,
+    )
     .execute(&mut transaction)
     .await?;
 
+    let players = ger_players(game.id, user_id, &mut transaction).await?;
+
+    let teams = list_teams(game.id, &players, &mut transaction).await?;
+
+    let boards = if options.boards {
+        list_boards(game.id, &players, &mut transaction).await?
+    } else {
+        Vec::new()
+    };
+
     transaction.commit().await?;
 
-    Ok(())
+    Ok(Json(SpectatorGameOut {
+        id: game.id,
+        open: true,
+        access_code: game.access_code,
+        players,
+        teams,
+        boards,
+        win_patterns: game.win_patterns.0,
+        ends_at: game.ends_at,
+    }))
 }
 
 pub async fn join_game(
     user_id: Uuid,
     access_code: String,
+    team_id: Option<Uuid>,
     conn: &mut PgConnection,
 ) -> Result<Json<GameOut>> {
     let game = sqlx::query!(
         r#"
             select 
-                gt.id as game_template_id, 
                 g.id,
                 g.closed,
                 g.access_code,
-                g.grid_size
+                g.win_patterns as "win_patterns: PgJson<Vec<WinPatternKind>>",
+                g.locked,
+                g.max_players,
+                g.ends_at,
+                g.required_confirmations,
+                g.announcers as "announcers: Announcers",
+                g.caller,
+                g.board_visibility as "board_visibility: BoardVisibility",
+                exists (
+                    select from bingo.players p where p.game_id = g.id and p.user_id = $2
+                ) as "joined!",
+                (select count(*) from bingo.players p where p.game_id = g.id) as "player_amount!"
             from 
                 bingo.games as g
-            inner join
-                bingo.game_templates as gt on g.game_template_id = gt.id
             where 
                 g.access_code = $1
+            for update
         "#,
-        access_code
+        access_code,
+        user_id,
     )
     .fetch_one(&mut *conn)
     .await?;
 
-    let fields = create_fields_for_player(
-        game.game_template_id,
-        game.id,
-        user_id,
-        game.grid_size.into(),
-        &mut *conn,
-    )
-    .await?;
+    // players who already joined can always come back
+
+    if !game.joined {
+        if game.locked {
+            return Err(Error::Conflict("The game is locked.".to_string()));
+        }
+        if let Some(max_players) = game.max_players {
+            if game.player_amount >= max_players as i64 {
+                return Err(Error::Conflict("The game is full.".to_string()));
+            }
+        }
+    }
+
+    let fields = create_fields_for_player(game.id, user_id, &mut *conn).await?;
+
+    if let Some(team_id) = team_id {
+        set_team(game.id, user_id, team_id, &mut *conn).await?;
+    }
 
     let players = ger_players(game.id, user_id, &mut *conn).await?;
 
+    let teams = list_teams(game.id, &players, &mut *conn).await?;
+
     let username = players
         .iter()
         .find(|v| v.is_me)
@@ -249,5 +823,83 @@ pub async fn join_game(
         fields,
         players,
         username,
+        win_patterns: game.win_patterns.0,
+        ends_at: game.ends_at,
+        required_confirmations: game.required_confirmations,
+        announcers: game.announcers,
+        caller: game.caller,
+        teams,
+        board_visibility: game.board_visibility,
+    }))
+}
+
+#[test]
+fn test_validate_settings() {
+    let settings = |value: serde_json::Value| {
+        validate_settings(serde_json::from_value::<GameSettingsIn>(value).unwrap())
+    };
+    let template_id = Uuid::new_v4();
+
+    let defaults = settings(serde_json::json!({ "templateId": template_id })).unwrap();
+    assert_eq!((defaults.width, defaults.height), (5, 5));
+    assert_eq!(defaults.win_patterns, WinPatternKind::default_set());
+    assert_eq!(defaults.free_cell_caption, "Free");
+    assert!(defaults.ends_at.is_none());
+
+    // captions are limited in characters, not bytes
+
+    let caption = "ü".repeat(128);
+    let umlauts = settings(serde_json::json!({
+        "templateId": template_id,
+        "freeCell": true,
+        "freeCellCaption": caption,
+        "extraCaptions": [caption],
     }))
+    .unwrap();
+    assert_eq!(umlauts.free_cell_caption, caption);
+
+    let custom = settings(serde_json::json!({
+        "templateId": template_id,
+        "gridSize": 3,
+        "width": 5,
+        "freeCell": true,
+        "locked": true,
+        "maxPlayers": 4,
+        "timeLimitSeconds": 600,
+        "requiredConfirmations": 2,
+        "caller": true,
+        "drawIntervalSeconds": 30,
+        "teams": [" red ", "blue"],
+    }))
+    .unwrap();
+    assert_eq!((custom.width, custom.height), (5, 3));
+    assert_eq!(custom.caption_amount(), 14);
+    assert!(custom.locked);
+    assert_eq!(custom.max_players, Some(4));
+    assert_eq!(custom.required_confirmations, Some(2));
+    assert_eq!(custom.draw_interval_seconds, Some(30));
+    assert_eq!(custom.teams, vec!["red", "blue"]);
+    assert!(custom.ends_at.unwrap() > Utc::now() + Duration::minutes(9));
+
+    for invalid in [
+        serde_json::json!({ "templateId": template_id, "gridSize": 9 }),
+        serde_json::json!({ "templateId": template_id, "extraCaptions": ["ü".repeat(129)] }),
+        serde_json::json!({ "templateId": template_id, "gridSize": 4, "freeCell": true }),
+        serde_json::json!({ "templateId": template_id, "maxPlayers": 0 }),
+        serde_json::json!({ "templateId": template_id, "timeLimitSeconds": 10 }),
+        serde_json::json!({ "templateId": template_id, "requiredConfirmations": 0 }),
+        serde_json::json!({ "templateId": template_id, "drawIntervalSeconds": 30 }),
+        serde_json::json!({ "templateId": template_id, "teams": ["red"] }),
+        serde_json::json!({ "templateId": template_id, "teams": ["red", " red"] }),
+        serde_json::json!({ "templateId": template_id, "caller": true, "drawIntervalSeconds": 1 }),
+        serde_json::json!({ "templateId": template_id, "winPatterns": [] }),
+        serde_json::json!({ "templateId": template_id, "endsAt": Utc::now() }),
+        serde_json::json!({
+            "templateId": template_id,
+            "timeLimitSeconds": 600,
+            "endsAt": Utc::now() + Duration::hours(1),
+        }),
+    ] {
+        assert!(settings(invalid).is_err());
+    }
 }
@@ -0,0 +1,382 @@
+use crate::{
+    body::{Announcers, CallIn, CallOut},
+    error::{Error, Result},
+    handler::game::field::expired_error,
+    server::{AppState, Identity},
+};
+use axum::{
+    extract::{Extension, Path},
+    Json,
+};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+/// Announces that a caption has been said, like a caller in real bingo.
+/// Whether the game allows it, and for whom, is up to the host.
+pub async fn handle_announce_caption(
+    identity: Identity,
+    Path(game_id): Path<Uuid>,
+    Json(payload): Json<CallIn>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<CallOut>> {
+    let mut transaction = state.pool.begin().await?;
+    let user_id = identity.user_id;
+
+    let game = sqlx::query!(
+        r#"
+            select
+                g.id,
+                g.announcers as "announcers: Announcers",
+                g.created_by = $2 as "is_host!",
+                coalesce(g.ends_at <= now(), false) as "expired!"
+            from bingo.games g
+            where
+                g.id = $1
+                and g.closed = false
+                and (
+                    g.created_by = $2
+                    or exists (
+                        select from bingo.players p where p.game_id = g.id and p.user_id = $2
+                    )
+                )
+            for update
+        "#,
+        game_id,
+        user_id,
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    match game.announcers {
+        Announcers::Nobody => {
+            return Err(Error::BadRequest(
+                "Captions can not be announced in this game.".to_string(),
+            ))
+        }
+        Announcers::Host if !game.is_host => {
+            return Err(Error::BadRequest(
+                "Only the host can announce captions in this game.".to_string(),
+            ))
+        }
+        _ => {}
+    }
+
+    if game.expired {
+        return Err(expired_error());
+    }
+
+    // excluded captions and ad-hoc captions of other games are not part of the game
+
+    sqlx::query!(
+        r#"
+            select ft.id
+            from
+                bingo.field_templates ft
+                join bingo.games g on g.id = $2
+            where
+                ft.id = $1
+                and ft.game_template_id = g.game_template_id
+                and (ft.game_id is null or ft.game_id = g.id)
+                and ft.id <> all(g.excluded_captions)
+        "#,
+        payload.field_template_id,
+        game.id,
+    )
+    .fetch_optional(&mut transaction)
+    .await?
+    .ok_or_else(|| Error::BadRequest("The caption is not part of the game.".to_string()))?;
+
+    call_caption(
+        game.id,
+        payload.field_template_id,
+        Some(user_id),
+        &mut transaction,
+    )
+    .await?;
+
+    let call = list_calls(game.id, &mut transaction)
+        .await?
+        .into_iter()
+        .find(|v| v.field_template_id == payload.field_template_id)
+        .ok_or(Error::InternalServer)?;
+
+    transaction.commit().await?;
+
+    Ok(Json(call))
+}
+
+/// Lets the host of a caller game draw the next caption.
+pub async fn handle_draw_caption(
+    identity: Identity,
+    Path(game_id): Path<Uuid>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<CallOut>> {
+    let mut transaction = state.pool.begin().await?;
+
+    let game = sqlx::query!(
+        r#"
+            select id, caller, coalesce(ends_at <= now(), false) as "expired!"
+            from bingo.games
+            where id = $1 and created_by = $2 and closed = false
+            for update
+        "#,
+        game_id,
+        identity.user_id,
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    if !game.caller {
+        return Err(Error::BadRequest(
+            "Captions can only be drawn in caller games.".to_string(),
+        ));
+    }
+    if game.expired {
+        return Err(expired_error());
+    }
+
+    let call = draw_caption(game.id, &mut transaction)
+        .await?
+        .ok_or_else(|| Error::Conflict("All captions have been drawn.".to_string()))?;
+
+    transaction.commit().await?;
+
+    Ok(Json(call))
+}
+
+/// Records a random caption of the game that has not been called yet.
+/// Players check drawn captions themselves. Returns nothing once all captions are drawn.
+pub async fn draw_caption(game_id: Uuid, conn: &mut PgConnection) -> Result<Option<CallOut>> {
+    let drawn = sqlx::query!(
+        r#"
+            insert into bingo.calls (game_id, field_template_id, drawn)
+            select g.id, ft.id, true
+            from
+                bingo.games g
+                join bingo.field_templates ft on ft.game_template_id = g.game_template_id
+            where
+                g.id = $1
+                and (ft.game_id is null or ft.game_id = g.id)
+                and ft.id <> all(g.excluded_captions)
+                and not exists (
+                    select from bingo.calls c
+                    where c.game_id = g.id and c.field_template_id = ft.id
+                )
+            order by random()
+            limit 1
+            returning field_template_id
+        "#,
+        game_id,
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    match drawn {
+        Some(drawn) => Ok(list_calls(game_id, &mut *conn)
+            .await?
+            .into_iter()
+            .find(|v| v.field_template_id == drawn.field_template_id)),
+        None => Ok(None),
+    }
+}
+
+/// Checks the caption on every card that has it. Announced checks need no confirmations.
+/// All notifications are folded into one, so sockets are refreshed only once.
+pub async fn call_caption(
+    game_id: Uuid,
+    field_template_id: Uuid,
+    user_id: Option<Uuid>,
+    conn: &mut PgConnection,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+            insert into bingo.calls (game_id, field_template_id, "user_id")
+            values ($1, $2, $3)
+            on conflict do nothing
+            returning id
+        "#,
+        game_id,
+        field_template_id,
+        user_id,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| Error::Conflict("The caption has already been announced.".to_string()))?;
+
+    sqlx::query!(
+        r#"
+            update bingo.fields
+            set
+                checked = true,
+                confirmed = true,
+                version = version + (not checked) :: integer
+            where
+                game_id = $1
+                and field_template_id = $2
+                and not (checked and confirmed)
+        "#,
+        game_id,
+        field_template_id,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn list_calls(game_id: Uuid, conn: &mut PgConnection) -> Result<Vec<CallOut>> {
+    let calls = sqlx::query!(
+        r#"
+            select
+                c.field_template_id,
+                ft.caption,
+                c.user_id as "user_id?",
+                p.username as "username?",
+                c.created_at,
+                c.drawn
+            from
+                bingo.calls c
+                join bingo.field_templates ft on ft.id = c.field_template_id
+                left join bingo.players p on p.game_id = c.game_id and p.user_id = c.user_id
+            where c.game_id = $1
+            order by c.created_at desc
+        "#,
+        game_id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|v| CallOut {
+        field_template_id: v.field_template_id,
+        caption: v.caption,
+        user_id: v.user_id,
+        username: v.username,
+        called_at: v.created_at,
+        drawn: v.drawn,
+    })
+    .collect::<Vec<CallOut>>();
+
+    Ok(calls)
+}
+
+#[tokio::test]
+#[ignore = "needs a database, set DATABASE_URL"]
+async fn test_call_caption() {
+    use sqlx::Connection;
+
+    let mut conn = super::test_connection().await;
+    let mut transaction = conn.begin().await.unwrap();
+
+    // everyone has the same captions, so each call checks one field per card
+
+    let settings = serde_json::json!({ "cardMode": "sameCaptions", "announcers": "everyone" });
+    let (game_id, host_id) = super::test_game(settings, &mut transaction).await;
+    let player_id = super::test_player(game_id, &mut transaction).await;
+
+    let fields = |field_template_id: Uuid| {
+        sqlx::query_as::<_, (Uuid, bool, bool, i32)>(
+            r#"
+                select "user_id", checked, confirmed, version from bingo.fields
+                where game_id = $1 and field_template_id = $2
+                order by "user_id"
+            "#,
+        )
+        .bind(game_id)
+        .bind(field_template_id)
+    };
+
+    let captions = sqlx::query_scalar::<_, Uuid>(
+        "select field_template_id from bingo.fields where game_id = $1 and user_id = $2 limit 2",
+    )
+    .bind(game_id)
+    .bind(host_id)
+    .fetch_all(&mut transaction)
+    .await
+    .unwrap();
+
+    // a field checked before the call keeps its version
+
+    sqlx::query(
+        r#"
+            update bingo.fields set checked = true, version = version + 1
+            where game_id = $1 and "user_id" = $2 and field_template_id = $3
+        "#,
+    )
+    .bind(game_id)
+    .bind(player_id)
+    .bind(captions[0])
+    .execute(&mut transaction)
+    .await
+    .unwrap();
+
+    call_caption(game_id, captions[0], Some(host_id), &mut transaction)
+        .await
+        .unwrap();
+
+    let called = fields(captions[0])
+        .fetch_all(&mut transaction)
+        .await
+        .unwrap();
+    assert_eq!(called.len(), 2);
+    assert!(called
+        .iter()
+        .all(|(_, checked, confirmed, version)| *checked && *confirmed && *version == 1));
+
+    let uncalled = fields(captions[1])
+        .fetch_all(&mut transaction)
+        .await
+        .unwrap();
+    assert!(uncalled.iter().all(|v| !v.1 && !v.2 && v.3 == 0));
+
+    // captions are called once, players joining later get them checked
+
+    assert!(matches!(
+        call_caption(game_id, captions[0], None, &mut transaction).await,
+        Err(Error::Conflict(_))
+    ));
+
+    let late_id = super::test_player(game_id, &mut transaction).await;
+    let called = fields(captions[0])
+        .fetch_all(&mut transaction)
+        .await
+        .unwrap();
+    assert!(called
+        .iter()
+        .any(|(user_id, checked, confirmed, _)| *user_id == late_id && *checked && *confirmed));
+
+    assert_eq!(
+        list_calls(game_id, &mut transaction).await.unwrap().len(),
+        1
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs a database, set DATABASE_URL"]
+async fn test_draw_caption() {
+    use sqlx::Connection;
+
+    let mut conn = super::test_connection().await;
+    let mut transaction = conn.begin().await.unwrap();
+
+    let settings = serde_json::json!({ "caller": true, "extraCaptions": ["extra"] });
+    let (game_id, _) = super::test_game(settings, &mut transaction).await;
+
+    // each caption of the template and the game is drawn once, then the caller runs dry
+
+    let mut drawn = Vec::new();
+    while let Some(call) = draw_caption(game_id, &mut transaction).await.unwrap() {
+        assert!(call.drawn);
+        assert!(!drawn.contains(&call.field_template_id));
+        drawn.push(call.field_template_id);
+    }
+
+    assert_eq!(drawn.len(), 31);
+    assert!(draw_caption(game_id, &mut transaction)
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        list_calls(game_id, &mut transaction).await.unwrap().len(),
+        31
+    );
+}
@@ -0,0 +1,1004 @@
+use crate::{
+    body::{CaptionRule, CardCheckOut, CardMode, DealIn, FieldOut},
+    error::{Error, Result},
+    handler::game::{
+        access::validate_grid,
+        field::list_fields,
+        rule::{max_per_card, min_per_card, row_categories},
+    },
+    server::{AppState, Identity, OptionalJson},
+};
+use api::pattern::WinPatternKind;
+use axum::{
+    extract::{Extension, Path},
+    Json,
+};
+use rand::{seq::SliceRandom, thread_rng, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use sqlx::{types::Json as PgJson, PgConnection};
+use uuid::Uuid;
+
+/// Lets the host check whether a player's card is the one derived from the game's seed.
+pub async fn handle_verify_card(
+    identity: Identity,
+    Path((game_id, user_id)): Path<(Uuid, Uuid)>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<CardCheckOut>> {
+    let mut conn = state.pool.acquire().await?;
+
+    let game = hosted_game(game_id, identity.user_id, &mut conn).await?;
+
+    let card = sqlx::query!(
+        r#"
+            select f.field_template_id
+            from bingo.fields f
+            where f.game_id = $1 and f.user_id = $2
+            order by f.position
+        "#,
+        game.id,
+        user_id,
+    )
+    .fetch_all(&mut conn)
+    .await?
+    .into_iter()
+    .map(|v| v.field_template_id)
+    .collect::<Vec<Option<Uuid>>>();
+
+    if card.is_empty() {
+        return Err(Error::NotFound);
+    }
+
+    Ok(Json(CardCheckOut {
+        user_id,
+        seed: game.seed.to_string(),
+        valid: card == deal_card(game.id, user_id, &mut conn).await?,
+    }))
+}
+
+/// Replaces a player's card with the one derived from the game's seed.
+/// Captions that remain on the card keep their checked state.
+pub async fn handle_regenerate_card(
+    identity: Identity,
+    Path((game_id, user_id)): Path<(Uuid, Uuid)>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<Vec<Vec<FieldOut>>>> {
+    let mut transaction = state.pool.begin().await?;
+
+    let game = hosted_game(game_id, identity.user_id, &mut transaction).await?;
+
+    if game.closed {
+        return Err(Error::BadRequest(
+            "Cards of finished games can not be changed.".to_string(),
+        ));
+    }
+
+    let checked = sqlx::query!(
+        r#"
+            select f.field_template_id as "field_template_id!"
+            from bingo.fields f
+            where
+                f.game_id = $1
+                and f.user_id = $2
+                and f.checked
+                and f.field_template_id is not null
+        "#,
+        game.id,
+        user_id,
+    )
+    .fetch_all(&mut transaction)
+    .await?
+    .into_iter()
+    .map(|v| v.field_template_id)
+    .collect::<Vec<Uuid>>();
+
+    sqlx::query!(
+        "select from bingo.players where game_id = $1 and user_id = $2",
+        game.id,
+        user_id
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    let card = deal_card(game.id, user_id, &mut transaction).await?;
+
+    sqlx::query!(
+        "delete from bingo.fields where game_id = $1 and user_id = $2",
+        game.id,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    insert_card(game.id, user_id, &card, &checked, &mut transaction).await?;
+
+    let fields = list_fields(game.id, user_id, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(fields))
+}
+
+/// Deals new cards to every player of a running game, optionally with a new grid size.
+/// All progress is reset. Sockets are refreshed through the update of the game row.
+pub async fn handle_redeal_cards(
+    identity: Identity,
+    Path(game_id): Path<Uuid>,
+    OptionalJson(payload): OptionalJson<DealIn>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<Vec<Vec<FieldOut>>>> {
+    let mut transaction = state.pool.begin().await?;
+    let user_id = identity.user_id;
+
+    let game = sqlx::query!(
+        r#"
+            select
+                g.id,
+                g.grid_width,
+                g.grid_height,
+                g.free_cell,
+                g.win_patterns as "win_patterns: PgJson<Vec<WinPatternKind>>",
+                cardinality(g.pinned_captions) + (
+                    select count(*) from bingo.field_templates ft where ft.game_id = g.id
+                ) as "pinned_amount!"
+            from bingo.games g
+            where g.id = $1 and g.created_by = $2 and g.closed = false
+            for update
+        "#,
+        game_id,
+        user_id,
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    let (width, height) = redeal_grid(
+        &payload,
+        (game.grid_width as i64, game.grid_height as i64),
+        game.free_cell,
+        &game.win_patterns,
+        game.pinned_amount,
+    )?;
+
+    // a new seed, so that cards actually change
+
+    sqlx::query!(
+        "update bingo.games set grid_width = $2, grid_height = $3, seed = $4 where id = $1",
+        game.id,
+        width as i32,
+        height as i32,
+        thread_rng().gen::<i64>(),
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    let user_ids = sqlx::query!(
+        r#"select "user_id" from bingo.players where game_id = $1"#,
+        game.id
+    )
+    .fetch_all(&mut transaction)
+    .await?
+    .into_iter()
+    .map(|v| v.user_id)
+    .collect::<Vec<Uuid>>();
+
+    sqlx::query!("delete from bingo.fields where game_id = $1", game.id)
+        .execute(&mut transaction)
+        .await?;
+
+    sqlx::query!("delete from bingo.calls where game_id = $1", game.id)
+        .execute(&mut transaction)
+        .await?;
+
+    for player_id in user_ids {
+        let card = deal_card(game.id, player_id, &mut transaction).await?;
+        insert_card(game.id, player_id, &card, &[], &mut transaction).await?;
+    }
+
+    let fields = list_fields(game.id, user_id, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(fields))
+}
+
+/// Grid size of re-dealt cards, which defaults to the current one. It has to fit
+/// the game's free cell, win patterns and pinned captions.
+fn redeal_grid(
+    payload: &DealIn,
+    (width, height): (i64, i64),
+    free_cell: bool,
+    win_patterns: &[WinPatternKind],
+    pinned_amount: i64,
+) -> Result<(i64, i64)> {
+    let width = payload.width.unwrap_or(width);
+    let height = payload.height.unwrap_or(height);
+
+    validate_grid((width, height), free_cell, win_patterns, pinned_amount)?;
+
+    Ok((width, height))
+}
+
+struct HostedGame {
+    id: Uuid,
+    seed: i64,
+    closed: bool,
+}
+
+async fn hosted_game(game_id: Uuid, user_id: Uuid, conn: &mut PgConnection) -> Result<HostedGame> {
+    let game = sqlx::query!(
+        "select id, seed, closed from bingo.games where id = $1 and created_by = $2",
+        game_id,
+        user_id,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(HostedGame {
+        id: game.id,
+        seed: game.seed,
+        closed: game.closed,
+    })
+}
+
+/// Derives the card of a player from the game's seed and settings.
+pub async fn deal_card(
+    game_id: Uuid,
+    user_id: Uuid,
+    conn: &mut PgConnection,
+) -> Result<Vec<Option<Uuid>>> {
+    let game = sqlx::query!(
+        r#"
+            select
+                g.grid_width,
+                g.grid_height,
+                g.free_cell,
+                g.card_mode as "card_mode: CardMode",
+                g.seed,
+                g.win_patterns as "win_patterns: PgJson<Vec<WinPatternKind>>",
+                gt.rules as "rules: PgJson<Vec<CaptionRule>>"
+            from
+                bingo.games g
+                join bingo.game_templates gt on gt.id = g.game_template_id
+            where g.id = $1
+        "#,
+        game_id,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    let (width, height) = (game.grid_width as i64, game.grid_height as i64);
+
+    let captions = sqlx::query!(
+        r#"
+            select
+                ft.id,
+                ft.weight,
+                ft.category,
+                ft.game_id is not null or ft.id = any(g.pinned_captions) as "pinned!"
+            from
+                bingo.games g
+                join bingo.field_templates ft on ft.game_template_id = g.game_template_id
+            where
+                g.id = $1
+                and (ft.game_id is null or ft.game_id = g.id)
+                and ft.id <> all(g.excluded_captions)
+        "#,
+        game_id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|v| Caption {
+        id: v.id,
+        weight: v.weight,
+        category: v.category,
+        pinned: v.pinned,
+    })
+    .collect::<Vec<Caption>>();
+
+    let grid = Grid {
+        width: width as usize,
+        height: height as usize,
+        free_position: free_position(width, height, game.free_cell),
+    };
+
+    validate_grid(
+        (width, height),
+        game.free_cell,
+        &game.win_patterns,
+        captions.iter().filter(|v| v.pinned).count() as i64,
+    )?;
+
+    if captions.len() < grid.caption_amount() {
+        return Err(Error::BadRequest(format!(
+            "Template has not enough fields for grid size of {width}x{height}"
+        )));
+    }
+
+    deal(
+        &captions,
+        &game.rules,
+        grid,
+        game.card_mode,
+        game.seed,
+        user_id,
+    )
+}
+
+/// Inserts a card row by row. Free cells and captions that have been announced already
+/// are checked from the start. Drawn captions are left to the players.
+pub async fn insert_card(
+    game_id: Uuid,
+    user_id: Uuid,
+    card: &[Option<Uuid>],
+    checked: &[Uuid],
+    conn: &mut PgConnection,
+) -> Result<()> {
+    let called = sqlx::query!(
+        "select field_template_id from bingo.calls where game_id = $1 and drawn = false",
+        game_id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|v| v.field_template_id)
+    .collect::<Vec<Uuid>>();
+
+    for (i, field_template_id) in card.iter().enumerate() {
+        let (checked, confirmed) = initial_state(*field_template_id, &called, checked);
+
+        sqlx::query!(
+            r#"
+                insert into bingo.fields (
+                    game_id,
+                    field_template_id,
+                    position,
+                    user_id,
+                    free,
+                    checked,
+                    confirmed
+                )
+                values ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            game_id,
+            *field_template_id,
+            i as i16,
+            user_id,
+            field_template_id.is_none(),
+            checked,
+            confirmed,
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Whether a new field is checked and confirmed. The free cell is always checked,
+/// announced captions are checked and confirmed, and checks kept from a previous card
+/// need to be confirmed again.
+fn initial_state(
+    field_template_id: Option<Uuid>,
+    called: &[Uuid],
+    checked: &[Uuid],
+) -> (bool, bool) {
+    match field_template_id {
+        None => (true, false),
+        Some(id) if called.contains(&id) => (true, true),
+        Some(id) => (checked.contains(&id), false),
+    }
+}
+
+/// A field template as drawn onto cards.
+#[derive(Debug, Clone)]
+struct Caption {
+    id: Uuid,
+    weight: i32,
+    category: Option<String>,
+    /// Pinned captions are on every card, regardless of weights and rules.
+    pinned: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Grid {
+    width: usize,
+    height: usize,
+    free_position: Option<usize>,
+}
+
+impl Grid {
+    fn caption_amount(&self) -> usize {
+        self.width * self.height - self.free_position.is_some() as usize
+    }
+}
+
+/// Picks and places the captions of a card. The result only depends on the arguments,
+/// so a card can be reproduced as long as the template and the game's settings are unchanged.
+///
+/// Individual cards are derived from the seed and the player, shared cards from the seed alone.
+/// With shared captions, only their order depends on the player.
+fn deal(
+    captions: &[Caption],
+    rules: &[CaptionRule],
+    grid: Grid,
+    card_mode: CardMode,
+    seed: i64,
+    user_id: Uuid,
+) -> Result<Vec<Option<Uuid>>> {
+    // the database returns templates in no particular order
+    let mut captions = captions.to_vec();
+    captions.sort_by_key(|v| v.id);
+
+    let (selection_user_id, placement_user_id) = reference_users(card_mode, user_id);
+
+    // without weights, rules and pins, cards are dealt as before those existed,
+    // so they stay verifiable
+
+    if rules.is_empty()
+        && captions
+            .iter()
+            .all(|v| v.weight == captions[0].weight && !v.pinned)
+    {
+        let mut ids = captions.iter().map(|v| v.id).collect::<Vec<Uuid>>();
+        ids.shuffle(&mut rng(seed, selection_user_id));
+        ids.truncate(grid.caption_amount());
+
+        if card_mode == CardMode::SameCaptions {
+            ids.shuffle(&mut rng(seed, user_id));
+        }
+
+        return Ok(layout(ids, grid.free_position));
+    }
+
+    let picked = pick(captions, rules, grid, &mut rng(seed, selection_user_id))?;
+
+    place(picked, rules, grid, &mut rng(seed, placement_user_id))
+}
+
+/// Users whose random numbers pick and place the captions of a player's card.
+/// Shared parts are derived for the nil user, so they are the same for everyone.
+fn reference_users(card_mode: CardMode, user_id: Uuid) -> (Uuid, Uuid) {
+    match card_mode {
+        CardMode::Individual => (user_id, user_id),
+        CardMode::SameCard => (Uuid::nil(), Uuid::nil()),
+        CardMode::SameCaptions => (Uuid::nil(), user_id),
+    }
+}
+
+/// Draws captions with odds proportional to their weight,
+/// after the pinned ones and the ones the rules require.
+fn pick(
+    pool: Vec<Caption>,
+    rules: &[CaptionRule],
+    grid: Grid,
+    rng: &mut ChaCha8Rng,
+) -> Result<Vec<Caption>> {
+    let amount = grid.caption_amount();
+    let (mut picked, mut pool): (Vec<Caption>, Vec<Caption>) =
+        pool.into_iter().partition(|v| v.pinned);
+
+    let mut categories = pool
+        .iter()
+        .chain(&picked)
+        .filter_map(|v| v.category.as_deref())
+        .chain(rules.iter().flat_map(|v| v.categories()))
+        .map(|v| v.to_string())
+        .collect::<Vec<String>>();
+    categories.sort();
+    categories.dedup();
+
+    for category in &categories {
+        let min = min_per_card(rules, category, grid.height);
+
+        while count(&picked, category) < min {
+            let caption = draw(&mut pool, &picked, rules, rng, |v| {
+                v.category.as_deref() == Some(category.as_str())
+            })
+            .ok_or_else(|| unsatisfiable(grid))?;
+            picked.push(caption);
+        }
+    }
+
+    if picked.len() > amount {
+        return Err(unsatisfiable(grid));
+    }
+
+    while picked.len() < amount {
+        let caption =
+            draw(&mut pool, &picked, rules, rng, |_| true).ok_or_else(|| unsatisfiable(grid))?;
+        picked.push(caption);
+    }
+
+    Ok(picked)
+}
+
+/// Removes a random caption matching `filter` from the pool, skipping categories
+/// that already reached their maximum.
+fn draw(
+    pool: &mut Vec<Caption>,
+    picked: &[Caption],
+    rules: &[CaptionRule],
+    rng: &mut ChaCha8Rng,
+    filter: impl Fn(&Caption) -> bool,
+) -> Option<Caption> {
+    let candidates = pool
+        .iter()
+        .enumerate()
+        .filter(|(_, v)| filter(v))
+        .filter(|(_, v)| match &v.category {
+            Some(category) => max_per_card(rules, category)
+                .map(|max| count(picked, category) < max)
+                .unwrap_or(true),
+            None => true,
+        })
+        .map(|(i, v)| (i, v.weight))
+        .collect::<Vec<(usize, i32)>>();
+
+    let (i, _) = candidates.choose_weighted(rng, |v| v.1).ok()?;
+
+    Some(pool.remove(*i))
+}
+
+/// Shuffles captions into place, putting one caption of each row category into every row.
+fn place(
+    mut captions: Vec<Caption>,
+    rules: &[CaptionRule],
+    grid: Grid,
+    rng: &mut ChaCha8Rng,
+) -> Result<Vec<Option<Uuid>>> {
+    captions.shuffle(rng);
+
+    let mut card = vec![None; grid.width * grid.height];
+    let row_categories = row_categories(rules);
+
+    if !row_categories.is_empty() {
+        for y in 0..grid.height {
+            let mut slots = (y * grid.width..(y + 1) * grid.width)
+                .filter(|i| Some(*i) != grid.free_position)
+                .collect::<Vec<usize>>();
+            slots.shuffle(rng);
+
+            for category in &row_categories {
+                let (slot, i) = slots
+                    .pop()
+                    .zip(
+                        captions
+                            .iter()
+                            .position(|v| v.category.as_deref() == Some(*category)),
+                    )
+                    .ok_or_else(|| unsatisfiable(grid))?;
+                card[slot] = Some(captions.remove(i).id);
+            }
+        }
+    }
+
+    let mut captions = captions.into_iter();
+    for (i, field) in card.iter_mut().enumerate() {
+        if field.is_none() && Some(i) != grid.free_position {
+            *field = captions.next().map(|v| v.id);
+        }
+    }
+
+    Ok(card)
+}
+
+fn count(captions: &[Caption], category: &str) -> usize {
+    captions
+        .iter()
+        .filter(|v| v.category.as_deref() == Some(category))
+        .count()
+}
+
+fn unsatisfiable(grid: Grid) -> Error {
+    Error::BadRequest(format!(
+        "The template's rules can not be met on a {}x{} card.",
+        grid.width, grid.height
+    ))
+}
+
+/// ChaCha keeps its output stable across platforms and releases, unlike `StdRng`.
+fn rng(seed: i64, user_id: Uuid) -> ChaCha8Rng {
+    let mut bytes = [0; 32];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    bytes[8..24].copy_from_slice(user_id.as_bytes());
+
+    ChaCha8Rng::from_seed(bytes)
+}
+
+/// Places captions row by row, skipping the free cell.
+fn layout(captions: Vec<Uuid>, free_position: Option<usize>) -> Vec<Option<Uuid>> {
+    let mut field_template_ids = captions
+        .into_iter()
+        .map(Some)
+        .collect::<Vec<Option<Uuid>>>();
+
+    if let Some(free_position) = free_position {
+        field_template_ids.insert(free_position, None);
+    }
+
+    field_template_ids
+}
+
+/// Position of the pre-checked free cell, which is the center of boards with an odd width and height.
+fn free_position(width: i64, height: i64, free_cell: bool) -> Option<usize> {
+    if free_cell && width % 2 == 1 && height % 2 == 1 {
+        Some((height / 2 * width + width / 2) as usize)
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_free_position() {
+    assert_eq!(free_position(3, 3, true), Some(4));
+    assert_eq!(free_position(5, 5, true), Some(12));
+    assert_eq!(free_position(3, 5, true), Some(7));
+    assert_eq!(free_position(5, 3, true), Some(7));
+    assert_eq!(free_position(4, 4, true), None);
+    assert_eq!(free_position(4, 3, true), None);
+    assert_eq!(free_position(5, 5, false), None);
+}
+
+#[cfg(test)]
+fn captions(categories: &[(Option<&str>, i32)]) -> Vec<Caption> {
+    categories
+        .iter()
+        .enumerate()
+        .map(|(i, (category, weight))| Caption {
+            id: Uuid::from_u128(i as u128),
+            weight: *weight,
+            category: category.map(|v| v.to_string()),
+            pinned: false,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn grid(width: usize, height: usize, free_cell: bool) -> Grid {
+    Grid {
+        width,
+        height,
+        free_position: free_position(width as i64, height as i64, free_cell),
+    }
+}
+
+#[test]
+fn test_deal() {
+    let captions = captions(&[(None, 1); 12]);
+    let alice = Uuid::from_u128(1);
+    let bob = Uuid::from_u128(2);
+    let deal = |captions: &[Caption], grid, card_mode, seed, user_id| {
+        deal(captions, &[], grid, card_mode, seed, user_id).unwrap()
+    };
+    let numbers = |card: Vec<Option<Uuid>>| {
+        card.into_iter()
+            .map(|v| v.map(|v| v.as_u128()))
+            .collect::<Vec<Option<u128>>>()
+    };
+
+    // layouts must never change, otherwise existing cards can no longer be verified
+
+    let card = deal(&captions, grid(3, 3, true), CardMode::Individual, 42, alice);
+    assert_eq!(
+        numbers(card),
+        vec![
+            Some(3),
+            Some(7),
+            Some(8),
+            Some(10),
+            None,
+            Some(11),
+            Some(9),
+            Some(5),
+            Some(2)
+        ]
+    );
+
+    let card = deal(&captions, grid(2, 2, false), CardMode::Individual, -7, bob);
+    assert_eq!(numbers(card), vec![Some(7), Some(0), Some(1), Some(11)]);
+
+    // same inputs, same card, regardless of template order
+
+    let mut reversed = captions.clone();
+    reversed.reverse();
+    let individual = |captions: &[Caption], seed, user_id| {
+        deal(
+            captions,
+            grid(3, 3, false),
+            CardMode::Individual,
+            seed,
+            user_id,
+        )
+    };
+    assert_eq!(
+        individual(&captions, 42, alice),
+        individual(&reversed, 42, alice)
+    );
+    assert_ne!(
+        individual(&captions, 42, alice),
+        individual(&captions, 43, alice)
+    );
+    assert_ne!(
+        individual(&captions, 42, alice),
+        individual(&captions, 42, bob)
+    );
+
+    // shared modes
+
+    assert_eq!(
+        deal(&captions, grid(3, 3, true), CardMode::SameCard, 42, alice),
+        deal(&captions, grid(3, 3, true), CardMode::SameCard, 42, bob)
+    );
+
+    let mut a = deal(
+        &captions,
+        grid(3, 3, true),
+        CardMode::SameCaptions,
+        42,
+        alice,
+    );
+    let mut b = deal(&captions, grid(3, 3, true), CardMode::SameCaptions, 42, bob);
+    assert_ne!(a, b);
+    assert_eq!((a[4], b[4]), (None, None));
+    a.sort();
+    b.sort();
+    assert_eq!(a, b);
+}
+
+#[test]
+fn test_deal_with_rules() {
+    let alice = Uuid::from_u128(1);
+    let category_of = |captions: &[Caption], id: Option<Uuid>| {
+        id.and_then(|id| captions.iter().find(|v| v.id == id))
+            .and_then(|v| v.category.clone())
+    };
+
+    // heavy captions are drawn far more often
+
+    let weighted = captions(&[[(None, 100); 9], [(Some("light"), 1); 9]].concat());
+    let light = (0..50)
+        .flat_map(|seed| {
+            deal(
+                &weighted,
+                &[],
+                grid(3, 3, false),
+                CardMode::Individual,
+                seed,
+                alice,
+            )
+            .unwrap()
+        })
+        .filter(|v| category_of(&weighted, *v).is_some())
+        .count();
+    assert!(light < 25, "{light} light captions on 50 cards");
+
+    let categorized = captions(
+        &[
+            &[(Some("common"), 1); 20][..],
+            &[(Some("rare"), 1); 10],
+            &[(None, 1); 10],
+        ]
+        .concat(),
+    );
+
+    // at most two rare per card
+
+    let rules = [CaptionRule::MaxPerCard {
+        category: "rare".to_string(),
+        max: 2,
+    }];
+    for seed in 0..50 {
+        let card = deal(
+            &categorized,
+            &rules,
+            grid(5, 5, true),
+            CardMode::Individual,
+            seed,
+            alice,
+        )
+        .unwrap();
+        let rare = card
+            .iter()
+            .filter(|v| category_of(&categorized, **v).as_deref() == Some("rare"))
+            .count();
+        assert!(rare <= 2);
+        assert_eq!(card.iter().filter(|v| v.is_none()).count(), 1);
+    }
+
+    // one of each category per row
+
+    let rules = [CaptionRule::EachPerRow {
+        categories: vec!["common".to_string(), "rare".to_string()],
+    }];
+    for seed in 0..50 {
+        let card = deal(
+            &categorized,
+            &rules,
+            grid(5, 3, true),
+            CardMode::SameCaptions,
+            seed,
+            alice,
+        )
+        .unwrap();
+        for row in card.chunks(5) {
+            for category in ["common", "rare"] {
+                assert!(row
+                    .iter()
+                    .any(|v| category_of(&categorized, *v).as_deref() == Some(category)));
+            }
+        }
+    }
+
+    // pinned captions are always dealt, even in categories limited by rules
+
+    let mut pinned = categorized.clone();
+    for caption in pinned.iter_mut().skip(20).take(3) {
+        caption.pinned = true;
+    }
+    let rules = [CaptionRule::MaxPerCard {
+        category: "rare".to_string(),
+        max: 2,
+    }];
+    for seed in 0..50 {
+        let card = deal(
+            &pinned,
+            &rules,
+            grid(3, 3, false),
+            CardMode::Individual,
+            seed,
+            alice,
+        )
+        .unwrap();
+        for i in 20..23 {
+            assert!(card.contains(&Some(Uuid::from_u128(i))));
+        }
+    }
+
+    // rules that can not be met
+
+    let rules = [
+        CaptionRule::MaxPerCard {
+            category: "rare".to_string(),
+            max: 2,
+        },
+        CaptionRule::EachPerRow {
+            categories: vec!["rare".to_string()],
+        },
+    ];
+    assert!(deal(
+        &categorized,
+        &rules,
+        grid(3, 3, false),
+        CardMode::Individual,
+        42,
+        alice
+    )
+    .is_err());
+    let rules = [CaptionRule::MinPerCard {
+        category: "rare".to_string(),
+        min: 11,
+    }];
+    assert!(deal(
+        &categorized,
+        &rules,
+        grid(5, 5, false),
+        CardMode::Individual,
+        42,
+        alice
+    )
+    .is_err());
+}
+
+#[test]
+fn test_reference_users() {
+    let alice = Uuid::from_u128(1);
+
+    assert_eq!(reference_users(CardMode::Individual, alice), (alice, alice));
+    assert_eq!(
+        reference_users(CardMode::SameCard, alice),
+        (Uuid::nil(), Uuid::nil())
+    );
+    assert_eq!(
+        reference_users(CardMode::SameCaptions, alice),
+        (Uuid::nil(), alice)
+    );
+}
+
+#[test]
+fn test_card_modes_with_rules() {
+    let categorized = captions(&[[(Some("a"), 1); 10], [(Some("b"), 3); 10]].concat());
+    let rules = [CaptionRule::MaxPerCard {
+        category: "b".to_string(),
+        max: 4,
+    }];
+    let deal = |card_mode, user_id| {
+        deal(
+            &categorized,
+            &rules,
+            grid(3, 3, true),
+            card_mode,
+            42,
+            Uuid::from_u128(user_id),
+        )
+        .unwrap()
+    };
+    let sorted = |mut card: Vec<Option<Uuid>>| {
+        card.sort();
+        card
+    };
+
+    // weighted dealing follows the card mode just like plain dealing
+
+    assert_eq!(deal(CardMode::SameCard, 1), deal(CardMode::SameCard, 2));
+    assert_ne!(
+        sorted(deal(CardMode::Individual, 1)),
+        sorted(deal(CardMode::Individual, 2))
+    );
+
+    let (a, b) = (
+        deal(CardMode::SameCaptions, 1),
+        deal(CardMode::SameCaptions, 2),
+    );
+    assert_ne!(a, b);
+    assert_eq!(sorted(a), sorted(b));
+
+    // players share the captions of the nil user's card
+
+    assert_eq!(
+        sorted(deal(CardMode::SameCaptions, 1)),
+        sorted(deal(CardMode::Individual, 0))
+    );
+}
+
+#[test]
+fn test_redeal_grid() {
+    let size = |width: Option<i64>, height: Option<i64>| DealIn { width, height };
+    let lines = WinPatternKind::default_set();
+
+    // the current size is kept unless a new one is given
+
+    assert_eq!(
+        redeal_grid(&size(None, None), (5, 5), true, &lines, 0).unwrap(),
+        (5, 5)
+    );
+    assert_eq!(
+        redeal_grid(&size(Some(3), None), (5, 5), false, &lines, 0).unwrap(),
+        (3, 5)
+    );
+    assert_eq!(
+        redeal_grid(&size(Some(4), Some(2)), (3, 3), false, &lines, 8).unwrap(),
+        (4, 2)
+    );
+
+    assert!(redeal_grid(&size(Some(9), None), (5, 5), false, &lines, 0).is_err());
+    assert!(redeal_grid(&size(Some(1), Some(1)), (5, 5), false, &lines, 0).is_err());
+
+    // the free cell needs a center, the plus pattern as well
+
+    assert!(redeal_grid(&size(Some(4), None), (5, 5), true, &lines, 0).is_err());
+    assert!(redeal_grid(
+        &size(Some(4), Some(4)),
+        (5, 5),
+        false,
+        &[WinPatternKind::Plus],
+        0
+    )
+    .is_err());
+    assert!(redeal_grid(&size(Some(3), None), (3, 3), false, &[WinPatternKind::X], 0).is_ok());
+    assert!(redeal_grid(&size(Some(5), None), (3, 3), false, &[WinPatternKind::X], 0).is_err());
+
+    // pinned and ad-hoc captions must still fit, the free cell takes one place
+
+    assert!(redeal_grid(&size(Some(3), Some(3)), (5, 5), true, &lines, 8).is_ok());
+    assert!(redeal_grid(&size(Some(3), Some(3)), (5, 5), true, &lines, 9).is_err());
+}
+
+#[test]
+fn test_initial_state() {
+    let (a, b, c) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+
+    assert_eq!(initial_state(None, &[a], &[b]), (true, false));
+    assert_eq!(initial_state(Some(a), &[a], &[]), (true, true));
+    assert_eq!(initial_state(Some(a), &[a], &[a]), (true, true));
+    assert_eq!(initial_state(Some(b), &[a], &[b]), (true, false));
+    assert_eq!(initial_state(Some(c), &[a], &[b]), (false, false));
+}
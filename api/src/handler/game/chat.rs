@@ -0,0 +1,405 @@
+use crate::{
+    body::{ChatMessageIn, ChatMessageOut, ReactionIn, ReactionOut},
+    error::{Error, Result},
+    server::{AppState, Identity},
+};
+use axum::{
+    extract::{Extension, Path},
+    Json,
+};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+/// Emojis players can react with.
+pub const REACTIONS: [&str; 8] = ["👍", "😂", "🎉", "😮", "🙄", "🔥", "💯", "🤦"];
+
+pub async fn handle_send_message(
+    identity: Identity,
+    Path(game_id): Path<Uuid>,
+    Json(payload): Json<ChatMessageIn>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<ChatMessageOut>> {
+    let mut transaction = state.pool.begin().await?;
+
+    let content = validate_content(&payload.content)?;
+
+    let message = send_message(game_id, identity.user_id, content, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(message))
+}
+
+/// Players can send five messages per ten seconds.
+async fn send_message(
+    game_id: Uuid,
+    user_id: Uuid,
+    content: String,
+    conn: &mut PgConnection,
+) -> Result<ChatMessageOut> {
+    // lock the player so that messages sent at once are counted one after another
+
+    let player = sqlx::query!(
+        r#"
+            select p.username
+            from
+                bingo.players p
+                join bingo.games g on g.id = p.game_id
+            where p.game_id = $1 and p.user_id = $2 and g.closed = false
+            for update of p
+        "#,
+        game_id,
+        user_id,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let recent_messages = sqlx::query!(
+        r#"
+            select count(*) as "amount!" from bingo.messages
+            where
+                game_id = $1
+                and "user_id" = $2
+                and created_at > now() - interval '10 seconds'
+        "#,
+        game_id,
+        user_id,
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .amount;
+
+    if recent_messages >= 5 {
+        return Err(Error::TooManyRequests(
+            "Too many messages, please wait a moment.".to_string(),
+        ));
+    }
+
+    let message = sqlx::query!(
+        r#"
+            insert into bingo.messages (game_id, "user_id", "username", content)
+            values ($1, $2, $3, $4)
+            returning id, created_at, updated_at, revision
+        "#,
+        game_id,
+        user_id,
+        player.username,
+        content,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(ChatMessageOut {
+        id: message.id,
+        user_id,
+        username: player.username,
+        content,
+        reactions: Vec::new(),
+        deleted: false,
+        is_me: true,
+        created_at: message.created_at,
+        updated_at: message.updated_at,
+        revision: message.revision,
+    })
+}
+
+/// Hosts can delete messages of their games.
+pub async fn handle_delete_message(
+    identity: Identity,
+    Path((game_id, message_id)): Path<(Uuid, Uuid)>,
+    Extension(state): Extension<AppState>,
+) -> Result<()> {
+    let mut transaction = state.pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+            update bingo.messages m
+            set deleted_at = now(), updated_at = now()
+            from bingo.games g
+            where
+                m.id = $1
+                and m.game_id = $2
+                and m.deleted_at is null
+                and g.id = m.game_id
+                and g.created_by = $3
+            returning m.id
+        "#,
+        message_id,
+        game_id,
+        identity.user_id,
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    sqlx::query!(
+        "delete from bingo.reactions where message_id = $1",
+        message_id
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Adds the player's reaction to a message, or takes it back.
+pub async fn handle_toggle_reaction(
+    identity: Identity,
+    Path((game_id, message_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<ReactionIn>,
+    Extension(state): Extension<AppState>,
+) -> Result<()> {
+    let mut transaction = state.pool.begin().await?;
+    let user_id = identity.user_id;
+
+    if !REACTIONS.contains(&payload.emoji.as_str()) {
+        return Err(Error::BadRequest(format!(
+            "Reactions must be one of {}.",
+            REACTIONS.join(" ")
+        )));
+    }
+
+    // bumping the message lets sockets pick up the reaction
+
+    let message = sqlx::query!(
+        r#"
+            update bingo.messages m
+            set updated_at = now()
+            from bingo.players p
+            where
+                m.id = $1
+                and m.game_id = $2
+                and m.deleted_at is null
+                and p.game_id = m.game_id
+                and p.user_id = $3
+            returning m.id
+        "#,
+        message_id,
+        game_id,
+        user_id,
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    let removed = sqlx::query!(
+        r#"
+            delete from bingo.reactions
+            where message_id = $1 and "user_id" = $2 and emoji = $3
+            returning message_id
+        "#,
+        message.id,
+        user_id,
+        payload.emoji,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+
+    if removed.is_none() {
+        sqlx::query!(
+            r#"insert into bingo.reactions (message_id, "user_id", emoji) values ($1, $2, $3)"#,
+            message.id,
+            user_id,
+            payload.emoji,
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Latest revision of the game's chat, 0 without messages. Messages committed later
+/// always get a higher one.
+pub async fn latest_revision(game_id: Uuid, conn: &mut PgConnection) -> Result<i64> {
+    let revision = sqlx::query!(
+        r#"select coalesce(max(revision), 0) as "revision!" from bingo.messages where game_id = $1"#,
+        game_id
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .revision;
+
+    Ok(revision)
+}
+
+/// Messages with a higher revision than the given one, or the latest 50 messages without one.
+/// Ordered from old to new.
+pub async fn list_messages(
+    game_id: Uuid,
+    user_id: Uuid,
+    since: Option<i64>,
+    conn: &mut PgConnection,
+) -> Result<Vec<ChatMessageOut>> {
+    let messages = sqlx::query!(
+        r#"
+            select * from (
+                select
+                    m.id,
+                    m.user_id,
+                    coalesce(p.username, m.username) as "username!",
+                    m.content,
+                    m.deleted_at is not null as "deleted!",
+                    m.created_at,
+                    m.updated_at,
+                    m.revision
+                from
+                    bingo.messages m
+                    left join bingo.players p on p.game_id = m.game_id and p.user_id = m.user_id
+                where
+                    m.game_id = $1
+                    and case
+                        when $2 :: bigint is null then m.deleted_at is null
+                        else m.revision > $2
+                    end
+                order by m.created_at desc
+                limit case when $2 :: bigint is null then 50 end
+            ) as latest
+            order by created_at asc
+        "#,
+        game_id,
+        since,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let reactions = sqlx::query!(
+        r#"
+            select
+                message_id,
+                emoji,
+                count(*) as "amount!",
+                bool_or("user_id" = $2) as "reacted_by_me!"
+            from bingo.reactions
+            where message_id = any($1)
+            group by message_id, emoji
+            order by min(created_at)
+        "#,
+        &messages.iter().map(|v| v.id).collect::<Vec<Uuid>>(),
+        user_id,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let messages = messages
+        .into_iter()
+        .map(|v| ChatMessageOut {
+            id: v.id,
+            user_id: v.user_id,
+            username: v.username,
+            content: if v.deleted { String::new() } else { v.content },
+            reactions: reactions
+                .iter()
+                .filter(|r| r.message_id == v.id)
+                .map(|r| ReactionOut {
+                    emoji: r.emoji.clone(),
+                    amount: r.amount,
+                    reacted_by_me: r.reacted_by_me,
+                })
+                .collect(),
+            deleted: v.deleted,
+            is_me: v.user_id == user_id,
+            created_at: v.created_at,
+            updated_at: v.updated_at,
+            revision: v.revision,
+        })
+        .collect::<Vec<ChatMessageOut>>();
+
+    Ok(messages)
+}
+
+fn validate_content(content: &str) -> Result<String> {
+    let content = content.trim();
+
+    if content.is_empty() || content.chars().count() > 500 {
+        return Err(Error::BadRequest(
+            "Messages must have at least one and at most 500 characters.".to_string(),
+        ));
+    }
+
+    Ok(content.to_string())
+}
+
+#[test]
+fn test_validate_content() {
+    assert_eq!(validate_content("  synergy! ").unwrap(), "synergy!");
+    assert_eq!(
+        validate_content(&"ü".repeat(500)).unwrap().chars().count(),
+        500
+    );
+    assert!(validate_content(" \n ").is_err());
+    assert!(validate_content(&"a".repeat(501)).is_err());
+}
+
+#[tokio::test]
+#[ignore = "needs a database, set DATABASE_URL"]
+async fn test_concurrent_messages() {
+    use sqlx::Connection;
+
+    let mut conn = super::test_connection().await;
+    let mut other = super::test_connection().await;
+
+    let (game_id, host_id) = super::test_game(serde_json::json!({}), &mut conn).await;
+    let player_id = super::test_player(game_id, &mut conn).await;
+    let revision = latest_revision(game_id, &mut conn).await.unwrap();
+    assert_eq!(revision, 0);
+
+    // a message sent while another one is not committed yet waits for it,
+    // so a socket never skips the one committed last
+
+    let mut transaction = conn.begin().await.unwrap();
+    let first = send_message(game_id, host_id, "first".to_string(), &mut transaction)
+        .await
+        .unwrap();
+
+    let mut second = tokio::spawn(async move {
+        let mut transaction = other.begin().await.unwrap();
+        let message = send_message(game_id, player_id, "second".to_string(), &mut transaction)
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+        message
+    });
+
+    assert!(
+        tokio::time::timeout(std::time::Duration::from_millis(200), &mut second)
+            .await
+            .is_err()
+    );
+    transaction.commit().await.unwrap();
+    let second = second.await.unwrap();
+
+    assert!(revision < first.revision && first.revision < second.revision);
+
+    let contents = |messages: Vec<ChatMessageOut>| {
+        messages
+            .into_iter()
+            .map(|v| v.content)
+            .collect::<Vec<String>>()
+    };
+    assert_eq!(
+        contents(
+            list_messages(game_id, host_id, Some(revision), &mut conn)
+                .await
+                .unwrap()
+        ),
+        vec!["first", "second"]
+    );
+    assert_eq!(
+        contents(
+            list_messages(game_id, host_id, Some(first.revision), &mut conn)
+                .await
+                .unwrap()
+        ),
+        vec!["second"]
+    );
+    assert_eq!(
+        latest_revision(game_id, &mut conn).await.unwrap(),
+        second.revision
+    );
+    // the messages had to be committed, so they are removed again
+
+    super::test_cleanup(game_id, &[host_id, player_id], &mut conn).await;
+}
@@ -1,27 +1,48 @@
 use crate::{
-    body::FieldOut,
+    body::{
+        BoardOut, BoardVisibility, FieldIn, FieldOut, FieldStateOut, FieldUpdateIn, FieldsIn,
+        PendingCheckOut, PlayerOut,
+    },
     error::{Error, Result},
+    handler::game::{
+        card::{deal_card, insert_card},
+        player::joining_username,
+    },
     server::{AppState, Identity},
 };
-use axum::extract::{Extension, Path};
-use rand::{seq::SliceRandom, thread_rng};
+use axum::{
+    extract::{Extension, Path},
+    response::IntoResponse,
+    Json,
+};
+use http::{
+    header::{ETAG, IF_MATCH},
+    HeaderMap,
+};
 use sqlx::PgConnection;
+use std::collections::HashMap;
 use uuid::Uuid;
 
 pub async fn create_fields_for_player(
-    game_template_id: Uuid,
     game_id: Uuid,
     user_id: Uuid,
-    grid_size: i64,
     conn: &mut PgConnection,
 ) -> Result<Vec<Vec<FieldOut>>> {
+    // lock the game so that players joining at once do not all pick the same smallest team
+
+    let game = sqlx::query!(
+        "select grid_width, grid_height from bingo.games where id = $1 for update",
+        game_id,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    let (width, height) = (game.grid_width as i64, game.grid_height as i64);
+
     let existing_fields = sqlx::query!(
         r#"
             select 
                 f.id
             from bingo.fields as f
-            inner join bingo.field_templates as ft 
-                on f.field_template_id = ft.id
             where 
                 f.game_id = $1 and f.user_id = $2
             order by 
@@ -33,30 +54,8 @@ pub async fn create_fields_for_player(
     .fetch_all(&mut *conn)
     .await?;
 
-    if existing_fields.is_empty() || (existing_fields.len() as i64) < grid_size {
-        let mut field_template_ids = sqlx::query!(
-            r#"
-                select id from bingo.field_templates
-                where game_template_id = $1
-            "#,
-            game_template_id
-        )
-        .fetch_all(&mut *conn)
-        .await?
-        .iter()
-        .map(|v| v.id)
-        .collect::<Vec<Uuid>>();
-
-        if (field_template_ids.len() as i64) < grid_size {
-            return Err(Error::BadRequest(format!(
-                "Template has not enough fields for grid size of {grid_size}"
-            )));
-        }
-        if !(2..=8).contains(&grid_size) {
-            return Err(Error::BadRequest(
-                "Grid size must be grater than one and less than 9.".to_string(),
-            ));
-        }
+    if existing_fields.len() as i64 != width * height {
+        let card = deal_card(game_id, user_id, &mut *conn).await?;
 
         sqlx::query!(
             "delete from bingo.fields where id = any($1)",
@@ -64,6 +63,9 @@ pub async fn create_fields_for_player(
         )
         .execute(&mut *conn)
         .await?;
+
+        let username = joining_username(game_id, user_id, &mut *conn).await?;
+
         sqlx::query!(
             "delete from bingo.players where user_id = $1 and game_id = $2",
             user_id,
@@ -72,35 +74,28 @@ pub async fn create_fields_for_player(
         .execute(&mut *conn)
         .await?;
 
+        // new players join the smallest team
+
         sqlx::query!(
             r#"
-                insert into bingo.players ("user_id", game_id, "username")
-                values ($1, $2, $3)
+                insert into bingo.players ("user_id", game_id, "username", team_id)
+                values ($1, $2, $3, (
+                    select t.id from bingo.teams t
+                    where t.game_id = $2
+                    order by
+                        (select count(*) from bingo.players p where p.team_id = t.id),
+                        t.position
+                    limit 1
+                ))
             "#,
             user_id,
             game_id,
-            "Anonymous player",
+            username,
         )
         .execute(&mut *conn)
         .await?;
 
-        field_template_ids.shuffle(&mut thread_rng());
-        let field_template_ids = &field_template_ids[0..(grid_size * grid_size) as usize].to_vec();
-
-        for (i, field_template_id) in field_template_ids.iter().enumerate() {
-            sqlx::query!(
-                r#"
-                    insert into bingo.fields (game_id, field_template_id, position, user_id)
-                    values ($1, $2, $3, $4)
-                "#,
-                game_id,
-                field_template_id,
-                i as i16,
-                user_id,
-            )
-            .execute(&mut *conn)
-            .await?;
-        }
+        insert_card(game_id, user_id, &card, &[], &mut *conn).await?;
     }
 
     list_fields(game_id, user_id, &mut *conn).await
@@ -111,92 +106,758 @@ pub async fn list_fields(
     user_id: Uuid,
     conn: &mut PgConnection,
 ) -> Result<Vec<Vec<FieldOut>>> {
+    let mut cards = list_cards(game_id, &[user_id], conn).await?;
+
+    Ok(cards.remove(&user_id).unwrap_or_default())
+}
+
+/// Cards of the given players of a game, row by row.
+async fn list_cards(
+    game_id: Uuid,
+    user_ids: &[Uuid],
+    conn: &mut PgConnection,
+) -> Result<HashMap<Uuid, Vec<Vec<FieldOut>>>> {
     let fields = sqlx::query!(
         r#"
             select 
+                f.user_id,
                 f.id as id,
+                f.field_template_id,
                 f.checked as checked,
-                ft.caption as caption
+                f.free as free,
+                f.version as version,
+                coalesce(ft.caption, g.free_cell_caption) as "caption!",
+                f.checked
+                    and not f.free
+                    and not f.confirmed
+                    and g.required_confirmations is not null as "provisional!",
+                g.grid_width as grid_width
             from bingo.fields as f
-            inner join bingo.field_templates as ft 
+            inner join bingo.games as g
+                on f.game_id = g.id
+            left outer join bingo.field_templates as ft 
                 on f.field_template_id = ft.id
             where 
-                f.game_id = $1 and f.user_id = $2
+                f.game_id = $1 and f.user_id = any($2)
             order by 
-                position
+                f.user_id, position
         "#,
         game_id,
+        user_ids,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let width = fields.first().map(|v| v.grid_width as usize).unwrap_or(1);
+
+    let mut fields_by_user: HashMap<Uuid, Vec<FieldOut>> = HashMap::new();
+    for v in fields {
+        fields_by_user.entry(v.user_id).or_default().push(FieldOut {
+            id: v.id,
+            field_template_id: v.field_template_id,
+            text: v.caption,
+            position: 0,
+            checked: v.checked,
+            bingo: false,
+            free: v.free,
+            version: v.version,
+            provisional: v.provisional,
+        });
+    }
+
+    let cards = fields_by_user
+        .into_iter()
+        .map(|(user_id, fields)| {
+            let rows = fields
+                .chunks(width)
+                .map(|v| v.to_vec())
+                .collect::<Vec<Vec<FieldOut>>>();
+            (user_id, rows)
+        })
+        .collect();
+
+    Ok(cards)
+}
+
+/// Boards of the given players, in the same order. None unless the game shows full boards.
+/// All fields are fetched at once, games can have hundreds of players.
+pub async fn list_boards(
+    game_id: Uuid,
+    players: &[PlayerOut],
+    conn: &mut PgConnection,
+) -> Result<Vec<BoardOut>> {
+    let visibility = sqlx::query!(
+        r#"
+            select board_visibility as "board_visibility: BoardVisibility"
+            from bingo.games where id = $1
+        "#,
+        game_id
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .board_visibility;
+
+    if visibility != BoardVisibility::Full {
+        return Ok(Vec::new());
+    }
+
+    let mut cards = list_cards(
+        game_id,
+        &players.iter().map(|v| v.user_id).collect::<Vec<Uuid>>(),
+        &mut *conn,
+    )
+    .await?;
+
+    let boards = players
+        .iter()
+        .map(|player| BoardOut {
+            user_id: player.user_id,
+            username: player.username.clone(),
+            fields: cards.remove(&player.user_id).unwrap_or_default(),
+        })
+        .collect::<Vec<BoardOut>>();
+
+    Ok(boards)
+}
+
+pub async fn handle_update_field(
+    identity: Identity,
+    Path(id): Path<Uuid>,
+    Extension(state): Extension<AppState>,
+) -> Result<()> {
+    let mut transaction = state.pool.begin().await?;
+    let user_id = identity.user_id;
+
+    let field = locked_field(id, user_id, &mut transaction).await?;
+
+    check_change(&field, !field.checked)?;
+
+    sqlx::query!(
+        r#"
+            update bingo.fields set checked = not checked, confirmed = false, version = version + 1
+            where id = $1
+        "#,
+        id
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    reset_confirmations(&[id], &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Sets a field to the given state instead of toggling it, so retried requests are harmless.
+/// An expected version can be passed via `If-Match` header or request body.
+pub async fn handle_set_field(
+    identity: Identity,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(payload): Json<FieldIn>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse> {
+    let mut transaction = state.pool.begin().await?;
+    let user_id = identity.user_id;
+
+    let expected_version = match headers.get(IF_MATCH) {
+        Some(value) => parse_if_match(value.to_str().unwrap_or_default())?,
+        None => payload.version,
+    };
+
+    let field = locked_field(id, user_id, &mut transaction).await?;
+
+    check_change(&field, payload.checked)?;
+
+    if let Some(expected_version) = expected_version {
+        if expected_version != field.version {
+            return Err(Error::Conflict(format!(
+                "Field has been modified in the meantime (version {}).",
+                field.version
+            )));
+        }
+    }
+
+    // only write (and notify sockets) if the state actually changes
+
+    let version = if field.checked != payload.checked {
+        reset_confirmations(&[field.id], &mut transaction).await?;
+
+        sqlx::query!(
+            r#"
+                update bingo.fields set checked = $2, confirmed = false, version = version + 1
+                where id = $1
+                returning version
+            "#,
+            field.id,
+            payload.checked,
+        )
+        .fetch_one(&mut transaction)
+        .await?
+        .version
+    } else {
+        field.version
+    };
+
+    let field = FieldStateOut {
+        id: field.id,
+        checked: payload.checked,
+        version,
+    };
+
+    transaction.commit().await?;
+
+    Ok(([(ETAG, format!("\"{}\"", field.version))], Json(field)))
+}
+
+/// Applies many field updates of one game at once, e.g. changes queued by an offline client.
+/// Either all updates succeed or none, and since postgres folds identical notifications
+/// of a transaction into one, sockets are refreshed only once.
+pub async fn handle_set_fields(
+    identity: Identity,
+    Path(game_id): Path<Uuid>,
+    Json(payload): Json<FieldsIn>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<Vec<FieldStateOut>>> {
+    let mut transaction = state.pool.begin().await?;
+    let user_id = identity.user_id;
+
+    let ids = batch_ids(&payload.fields)?;
+
+    let fields = locked_fields(&ids, Some(game_id), user_id, &mut transaction).await?;
+
+    let result = apply_field_updates(&payload.fields, &fields)?;
+
+    let (changed_ids, changed_states): (Vec<Uuid>, Vec<bool>) = result
+        .iter()
+        .filter(|v| {
+            fields
+                .iter()
+                .any(|f| f.id == v.id && f.checked != v.checked)
+        })
+        .map(|v| (v.id, v.checked))
+        .unzip();
+
+    reset_confirmations(&changed_ids, &mut transaction).await?;
+
+    sqlx::query!(
+        r#"
+            update bingo.fields as f
+            set checked = u.checked, confirmed = false, version = f.version + 1
+            from unnest($1::uuid[], $2::boolean[]) as u(id, checked)
+            where f.id = u.id
+        "#,
+        &changed_ids,
+        &changed_states,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(result))
+}
+
+/// A field as locked for an update, together with the state of its game.
+struct LockedField {
+    id: Uuid,
+    checked: bool,
+    free: bool,
+    version: i32,
+    expired: bool,
+    undrawn: bool,
+}
+
+/// Locks a field of a running game of the player for an update.
+async fn locked_field(id: Uuid, user_id: Uuid, conn: &mut PgConnection) -> Result<LockedField> {
+    locked_fields(&[id], None, user_id, conn)
+        .await?
+        .pop()
+        .ok_or(Error::NotFound)
+}
+
+/// Locks fields of running games of the player for an update, optionally only those of one game.
+async fn locked_fields(
+    ids: &[Uuid],
+    game_id: Option<Uuid>,
+    user_id: Uuid,
+    conn: &mut PgConnection,
+) -> Result<Vec<LockedField>> {
+    let fields = sqlx::query!(
+        r#"
+            select
+                f.id,
+                f.checked,
+                f.free,
+                f.version,
+                coalesce(g.ends_at <= now(), false) as "expired!",
+                g.caller and not exists (
+                    select from bingo.calls c
+                    where c.game_id = g.id and c.field_template_id = f.field_template_id
+                ) as "undrawn!"
+            from 
+                bingo.fields as f
+            inner join 
+                bingo.games as g on f.game_id = g.id
+            where 
+                f.id = any($1)
+                and ($2 :: uuid is null or f.game_id = $2)
+                and f.user_id = $3
+                and g.closed = false
+            for update of f
+        "#,
+        ids,
+        game_id,
         user_id,
     )
     .fetch_all(&mut *conn)
     .await?
     .into_iter()
-    .map(|v| FieldOut {
+    .map(|v| LockedField {
         id: v.id,
-        text: v.caption,
-        position: 0,
         checked: v.checked,
-        bingo: false,
-    });
-
-    // TODO: figure our how to solve this with .chunks(5)
-
-    let mut result: Vec<Vec<FieldOut>> = Vec::new();
-    let mut v: Vec<FieldOut> = Vec::new();
-
-    if fields.len() > 0 {
-        let grid_size = (fields.len() as f32).sqrt() as usize;
-        for (i, field) in fields.enumerate() {
-            if i % grid_size == 0 {
-                if i == grid_size {
-                    result = vec![v]
-                } else {
-                    result.push(v);
-                }
-                v = vec![field];
-            } else {
-                v.push(field);
+        free: v.free,
+        version: v.version,
+        expired: v.expired,
+        undrawn: v.undrawn,
+    })
+    .collect::<Vec<LockedField>>();
+
+    Ok(fields)
+}
+
+/// Whether a player may set the field to the given state. Free fields never change,
+/// nothing changes after the deadline, and in caller games only drawn captions can be checked.
+fn check_change(field: &LockedField, checked: bool) -> Result<()> {
+    if field.free {
+        return Err(free_field_error());
+    }
+    if field.expired {
+        return Err(expired_error());
+    }
+    if checked && field.undrawn {
+        return Err(undrawn_error());
+    }
+
+    Ok(())
+}
+
+/// Checks the size of a batch and returns its field IDs. Each field may only appear once,
+/// otherwise the outcome would depend on the order of the updates.
+fn batch_ids(updates: &[FieldUpdateIn]) -> Result<Vec<Uuid>> {
+    if updates.len() > 128 {
+        return Err(Error::BadRequest(
+            "At most 128 fields can be updated at once.".to_string(),
+        ));
+    }
+
+    let ids = updates.iter().map(|v| v.id).collect::<Vec<Uuid>>();
+
+    if ids.iter().enumerate().any(|(i, id)| ids[..i].contains(id)) {
+        return Err(Error::BadRequest(
+            "Each field may only be updated once per request.".to_string(),
+        ));
+    }
+
+    Ok(ids)
+}
+
+/// Resulting states of a batch, in the order of the updates. Fails as a whole
+/// if one of the fields is missing, can not be changed or has another version than expected.
+/// Only fields whose state changes get a new version.
+fn apply_field_updates(
+    updates: &[FieldUpdateIn],
+    fields: &[LockedField],
+) -> Result<Vec<FieldStateOut>> {
+    let mut result = Vec::new();
+
+    for update in updates {
+        let field = fields
+            .iter()
+            .find(|v| v.id == update.id)
+            .ok_or(Error::NotFound)?;
+
+        check_change(field, update.checked)?;
+
+        if let Some(expected_version) = update.version {
+            if expected_version != field.version {
+                return Err(Error::Conflict(format!(
+                    "Field {} has been modified in the meantime (version {}).",
+                    field.id, field.version
+                )));
             }
         }
-    }
 
-    result.push(v);
+        result.push(FieldStateOut {
+            id: field.id,
+            checked: update.checked,
+            version: field.version + (field.checked != update.checked) as i32,
+        });
+    }
 
     Ok(result)
 }
 
-pub async fn handle_update_field(
+/// Confirms a provisional check of another player. An approval of the host is enough on its own.
+pub async fn handle_confirm_field(
     identity: Identity,
     Path(id): Path<Uuid>,
     Extension(state): Extension<AppState>,
 ) -> Result<()> {
-    let pool = &state.pool;
+    let mut transaction = state.pool.begin().await?;
     let user_id = identity.user_id;
 
-    let _game = sqlx::query!(
+    let field = sqlx::query!(
         r#"
             select
-                g.id as id,
-                g.game_template_id as game_template_id
-            from 
+                f.id,
+                f.game_id,
+                f.user_id,
+                f.checked,
+                f.confirmed,
+                f.free,
+                g.required_confirmations,
+                g.created_by = $2 as "is_host!",
+                coalesce(g.ends_at <= now(), false) as "expired!"
+            from
                 bingo.fields as f
-            inner join 
+            inner join
                 bingo.games as g on f.game_id = g.id
-            where 
-                f.id = $1 and f.user_id = $2 and g.closed = false
+            where
+                f.id = $1
+                and g.closed = false
+                and (
+                    g.created_by = $2
+                    or exists (
+                        select from bingo.players p where p.game_id = g.id and p.user_id = $2
+                    )
+                )
+            for update of f
         "#,
         id,
         user_id,
     )
-    .fetch_one(pool)
+    .fetch_one(&mut transaction)
     .await?;
 
+    let required_confirmations = field.required_confirmations.ok_or_else(|| {
+        Error::BadRequest("Checks of this game do not need confirmations.".to_string())
+    })?;
+
+    if field.user_id == user_id {
+        return Err(Error::BadRequest(
+            "Players can not confirm their own checks.".to_string(),
+        ));
+    }
+    if !field.checked || field.free {
+        return Err(Error::BadRequest(
+            "Only checked fields can be confirmed.".to_string(),
+        ));
+    }
+    if field.expired {
+        return Err(expired_error());
+    }
+    if field.confirmed {
+        return Ok(());
+    }
+
     sqlx::query!(
-        "update bingo.fields set checked = not checked where id = $1",
-        id
+        r#"
+            insert into bingo.confirmations (field_id, "user_id", game_id)
+            values ($1, $2, $3)
+            on conflict do nothing
+        "#,
+        field.id,
+        user_id,
+        field.game_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    let confirmations = sqlx::query!(
+        r#"select count(*) as "count!" from bingo.confirmations where field_id = $1"#,
+        field.id
+    )
+    .fetch_one(&mut transaction)
+    .await?
+    .count;
+
+    // the version only tracks the checked state, so it stays as it is
+
+    if confirmed_by(confirmations, required_confirmations, field.is_host) {
+        sqlx::query!(
+            "update bingo.fields set confirmed = true where id = $1",
+            field.id
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Checks are confirmed by enough other players, or by the host alone.
+fn confirmed_by(confirmations: i64, required_confirmations: i32, is_host: bool) -> bool {
+    is_host || confirmations >= required_confirmations as i64
+}
+
+/// Whether a checked field counts towards bingos. Checks of verified games count once
+/// they are confirmed, free cells need no confirmations.
+pub fn counts_as_hit(checked: bool, confirmed: bool, free: bool, verified: bool) -> bool {
+    checked && (confirmed || free || !verified)
+}
+
+/// Provisional checks of all players of a verified game.
+pub async fn pending_checks(
+    game_id: Uuid,
+    user_id: Uuid,
+    conn: &mut PgConnection,
+) -> Result<Vec<PendingCheckOut>> {
+    let checks = sqlx::query!(
+        r#"
+            select
+                f.id,
+                f.user_id,
+                p.username,
+                ft.caption,
+                g.required_confirmations as "required_confirmations!",
+                (
+                    select count(*) from bingo.confirmations c where c.field_id = f.id
+                ) as "confirmations!",
+                exists (
+                    select from bingo.confirmations c where c.field_id = f.id and c.user_id = $2
+                ) as "confirmed_by_me!"
+            from
+                bingo.fields as f
+            inner join bingo.games as g on f.game_id = g.id
+            inner join bingo.players as p on p.game_id = f.game_id and p.user_id = f.user_id
+            inner join bingo.field_templates as ft on f.field_template_id = ft.id
+            where
+                f.game_id = $1
+                and g.required_confirmations is not null
+                and f.checked
+                and not f.confirmed
+                and not f.free
+            order by
+                p.username, f.position
+        "#,
+        game_id,
+        user_id,
     )
-    .execute(pool)
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|v| PendingCheckOut {
+        field_id: v.id,
+        user_id: v.user_id,
+        username: v.username,
+        caption: v.caption,
+        confirmations: v.confirmations,
+        required_confirmations: v.required_confirmations,
+        confirmed_by_me: v.confirmed_by_me,
+        is_me: v.user_id == user_id,
+    })
+    .collect::<Vec<PendingCheckOut>>();
+
+    Ok(checks)
+}
+
+/// Checking or unchecking a field starts its verification over.
+async fn reset_confirmations(field_ids: &[Uuid], conn: &mut PgConnection) -> Result<()> {
+    sqlx::query!(
+        "delete from bingo.confirmations where field_id = any($1)",
+        field_ids
+    )
+    .execute(&mut *conn)
     .await?;
 
     Ok(())
 }
+
+fn free_field_error() -> Error {
+    Error::BadRequest("The free field can not be changed.".to_string())
+}
+
+/// Checks after the deadline of a timed game are too late, even if it is not closed yet.
+pub fn expired_error() -> Error {
+    Error::BadRequest("The game is over.".to_string())
+}
+
+/// Players of caller games can only check captions that have been drawn.
+fn undrawn_error() -> Error {
+    Error::BadRequest("Only drawn captions can be checked.".to_string())
+}
+
+/// Reads the expected field version from an `If-Match` header, e.g. `"3"` or `W/"3"`.
+/// A wildcard matches any version.
+fn parse_if_match(value: &str) -> Result<Option<i32>> {
+    let value = value.trim();
+
+    if value == "*" {
+        return Ok(None);
+    }
+
+    value
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .parse::<i32>()
+        .map(Some)
+        .map_err(|_| Error::BadRequest("If-Match must contain a field version.".to_string()))
+}
+
+#[test]
+fn test_parse_if_match() {
+    assert_eq!(parse_if_match("*").unwrap(), None);
+    assert_eq!(parse_if_match("\"3\"").unwrap(), Some(3));
+    assert_eq!(parse_if_match(" W/\"12\" ").unwrap(), Some(12));
+    assert_eq!(parse_if_match("7").unwrap(), Some(7));
+    assert!(parse_if_match("\"abc\"").is_err());
+    assert!(parse_if_match("").is_err());
+}
+
+#[test]
+fn test_check_change() {
+    let field = LockedField {
+        id: Uuid::nil(),
+        checked: false,
+        free: false,
+        version: 0,
+        expired: false,
+        undrawn: false,
+    };
+
+    assert!(check_change(&field, true).is_ok());
+    assert!(check_change(&field, false).is_ok());
+
+    // undrawn captions can still be unchecked, e.g. checks from before a re-deal
+
+    let undrawn = LockedField {
+        undrawn: true,
+        ..field
+    };
+    assert!(check_change(&undrawn, true).is_err());
+    assert!(check_change(&undrawn, false).is_ok());
+
+    for locked in [
+        LockedField {
+            free: true,
+            ..field
+        },
+        LockedField {
+            expired: true,
+            ..field
+        },
+    ] {
+        assert!(check_change(&locked, true).is_err());
+        assert!(check_change(&locked, false).is_err());
+    }
+}
+
+#[test]
+fn test_batch_ids() {
+    let update = |id: u128| FieldUpdateIn {
+        id: Uuid::from_u128(id),
+        checked: true,
+        version: None,
+    };
+
+    assert_eq!(
+        batch_ids(&[update(1), update(2)]).unwrap(),
+        vec![Uuid::from_u128(1), Uuid::from_u128(2)]
+    );
+    assert!(batch_ids(&[]).unwrap().is_empty());
+    assert!(batch_ids(&[update(1), update(2), update(1)]).is_err());
+    assert!(batch_ids(&(0..128).map(update).collect::<Vec<_>>()).is_ok());
+    assert!(batch_ids(&(0..129).map(update).collect::<Vec<_>>()).is_err());
+}
+
+#[test]
+fn test_apply_field_updates() {
+    let field = |id: u128, checked: bool, version: i32| LockedField {
+        id: Uuid::from_u128(id),
+        checked,
+        free: false,
+        version,
+        expired: false,
+        undrawn: false,
+    };
+    let update = |id: u128, checked: bool, version: Option<i32>| FieldUpdateIn {
+        id: Uuid::from_u128(id),
+        checked,
+        version,
+    };
+    let states = |result: Vec<FieldStateOut>| {
+        result
+            .into_iter()
+            .map(|v| (v.id.as_u128(), v.checked, v.version))
+            .collect::<Vec<_>>()
+    };
+    let fields = [field(1, false, 3), field(2, true, 7), field(3, false, 0)];
+
+    // only changed fields get a new version, the order of the request is kept
+
+    let result = apply_field_updates(
+        &[
+            update(3, true, None),
+            update(1, false, Some(3)),
+            update(2, true, Some(7)),
+        ],
+        &fields,
+    )
+    .unwrap();
+    assert_eq!(
+        states(result),
+        vec![(3, true, 1), (1, false, 3), (2, true, 7)]
+    );
+
+    // one outdated version or unknown field fails the whole batch
+
+    assert!(matches!(
+        apply_field_updates(
+            &[update(1, true, Some(3)), update(2, false, Some(6))],
+            &fields
+        ),
+        Err(Error::Conflict(_))
+    ));
+    assert!(matches!(
+        apply_field_updates(&[update(1, true, None), update(4, true, None)], &fields),
+        Err(Error::NotFound)
+    ));
+
+    let free = LockedField {
+        free: true,
+        ..field(5, true, 0)
+    };
+    assert!(apply_field_updates(&[update(5, true, None)], &[free]).is_err());
+}
+
+#[test]
+fn test_confirmed_by() {
+    assert!(!confirmed_by(0, 2, false));
+    assert!(!confirmed_by(1, 2, false));
+    assert!(confirmed_by(2, 2, false));
+    assert!(confirmed_by(3, 2, false));
+    assert!(confirmed_by(0, 2, true));
+    assert!(confirmed_by(1, 1, false));
+}
+
+#[test]
+fn test_counts_as_hit() {
+    // without verification, every check counts right away
+
+    assert!(counts_as_hit(true, false, false, false));
+    assert!(!counts_as_hit(false, false, false, false));
+
+    // provisional checks do not count until they are confirmed
+
+    assert!(!counts_as_hit(true, false, false, true));
+    assert!(counts_as_hit(true, true, false, true));
+    assert!(counts_as_hit(true, false, true, true));
+
+    // unchecking a confirmed field takes the hit back
+
+    assert!(!counts_as_hit(false, true, false, true));
+}
@@ -1,263 +1,124 @@
-use crate::error::{Error, Result};
-
 pub mod access;
+pub mod call;
+pub mod card;
+pub mod chat;
 pub mod field;
 pub mod player;
+pub mod result;
+pub mod rule;
 pub mod websocket;
 
-#[derive(Debug, Eq, PartialEq)]
-enum Direction {
-    Right,
-    Down,
-    RightDown,
-    RightUp,
-}
-
-fn bingos(fields: Vec<bool>) -> i32 {
-    match grid_size(fields.len()) {
-        Ok(grid_size) => {
-            let directions = vec![
-                Direction::Right,
-                Direction::Down,
-                Direction::RightDown,
-                Direction::RightUp,
-            ];
+/// Connection to the database of `DATABASE_URL` with all migrations applied.
+/// Tests that need a database are ignored by default, run them with `cargo test -- --ignored`.
+#[cfg(test)]
+pub async fn test_connection() -> sqlx::PgConnection {
+    use sqlx::Connection;
 
-            let mut border_fields = vec![];
-            for n in 0..fields.len() - 1 {
-                if n < grid_size || n % grid_size == 0 {
-                    border_fields.push(n);
-                }
-            }
+    let database_url = dotenv::var("DATABASE_URL").expect("DATABASE_URL is not set");
+    let mut conn = sqlx::PgConnection::connect(&database_url)
+        .await
+        .expect("database connection fails");
 
-            let mut bingo_amount = 0;
-            for direction in &directions {
-                for border_field in &border_fields {
-                    if walk(
-                        &fields,
-                        (*border_field) as i32,
-                        (*border_field) as i32,
-                        direction,
-                        0,
-                        0,
-                    ) == grid_size
-                    {
-                        bingo_amount += 1
-                    }
-                }
-            }
+    sqlx::migrate!("./migrations")
+        .run(&mut conn)
+        .await
+        .expect("running migrations fails");
 
-            bingo_amount
-        }
-        Err(_) => {
-            tracing::error!("invalid field amount: {}", fields.len());
-            0
-        }
-    }
+    conn
 }
 
-fn walk(
-    fields: &Vec<bool>,
-    start_idx: i32,
-    idx: i32,
-    direction: &Direction,
-    mut hits: usize,
-    mut n: usize,
-) -> usize {
-    n += 1;
-    let grid_size = grid_size(fields.len()).expect("grid_size fails") as i32;
-
-    // assert index is not out of bounds
-
-    if idx > (fields.len() - 1) as i32 || idx < 0 {
-        return hits;
-    }
-
-    // ensure to start walking right on fields of the left column only
-
-    if direction == &Direction::Right && (idx != start_idx && idx % grid_size == 0) {
-        return hits;
-    }
-
-    // count hits
-
-    if fields[idx as usize] {
-        hits += 1;
-    };
-
-    // return when walked enough steps
-
-    if n as i32 == grid_size || n > 1000 {
-        return hits;
-    }
-
-    // set next index according to walk direction
-
-    let next_idx = match direction {
-        Direction::Right => idx + 1,
-        Direction::Down => idx + grid_size,
-        Direction::RightDown => idx + grid_size + 1,
-        Direction::RightUp => {
-            if idx - (grid_size - 1) != 0 {
-                idx - (grid_size - 1)
-            } else {
-                -1
-            }
-        }
-    };
-
-    walk(fields, start_idx, next_idx, direction, hits, n)
+/// Starts a game of a new template with 30 captions, returns its ID and the host's ID.
+/// Settings default to the ones of `POST /games`.
+#[cfg(test)]
+pub async fn test_game(
+    mut settings: serde_json::Value,
+    conn: &mut sqlx::PgConnection,
+) -> (uuid::Uuid, uuid::Uuid) {
+    let user_id = test_user(conn).await;
+
+    let template_id = sqlx::query_scalar::<_, uuid::Uuid>(
+        "insert into bingo.game_templates (title, created_by) values ('test', $1) returning id",
+    )
+    .bind(user_id)
+    .fetch_one(&mut *conn)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"
+            insert into bingo.field_templates (game_template_id, caption)
+            select $1, 'caption ' || i from generate_series(1, 30) as i
+        "#,
+    )
+    .bind(template_id)
+    .execute(&mut *conn)
+    .await
+    .unwrap();
+
+    settings["templateId"] = serde_json::json!(template_id);
+    let settings = access::validate_settings(serde_json::from_value(settings).unwrap()).unwrap();
+    let game = access::create_game(user_id, &settings, None, &mut *conn)
+        .await
+        .unwrap();
+    field::create_fields_for_player(game.id, user_id, &mut *conn)
+        .await
+        .unwrap();
+
+    (game.id, user_id)
 }
 
-fn grid_size(field_amount: usize) -> Result<usize> {
-    let grid_size = (field_amount as f32).sqrt();
+/// Lets a new user join the game.
+#[cfg(test)]
+pub async fn test_player(game_id: uuid::Uuid, conn: &mut sqlx::PgConnection) -> uuid::Uuid {
+    let user_id = test_user(conn).await;
 
-    if !(2..10).any(|v| v as f32 == grid_size) {
-        return Err(Error::InternalServer);
-    }
+    field::create_fields_for_player(game_id, user_id, &mut *conn)
+        .await
+        .unwrap();
 
-    Ok(grid_size as usize)
+    user_id
 }
 
-#[test]
-fn test_grid_size() {
-    assert!(grid_size(0).is_err());
-    assert!(grid_size(1).is_err());
-    assert!(grid_size(2).is_err());
-    assert!(grid_size(3).is_err());
-    assert_eq!(grid_size(4).unwrap(), 2);
-    assert!(grid_size(5).is_err());
-    assert!(grid_size(6).is_err());
-    assert!(grid_size(7).is_err());
-    assert!(grid_size(8).is_err());
-    assert_eq!(grid_size(9).unwrap(), 3);
-    assert!(grid_size(10).is_err());
+/// Removes a game of `test_game` with everything committed for it and the given users.
+#[cfg(test)]
+pub async fn test_cleanup(
+    game_id: uuid::Uuid,
+    user_ids: &[uuid::Uuid],
+    conn: &mut sqlx::PgConnection,
+) {
+    let template_id = sqlx::query_scalar::<_, uuid::Uuid>(
+        "select game_template_id from bingo.games where id = $1",
+    )
+    .bind(game_id)
+    .fetch_one(&mut *conn)
+    .await
+    .unwrap();
+
+    for query in [
+        "delete from bingo.messages where game_id = $1",
+        "delete from bingo.calls where game_id = $1",
+        "delete from bingo.fields where game_id = $1",
+        "delete from bingo.players where game_id = $1",
+        "delete from bingo.teams where game_id = $1",
+        "delete from bingo.field_templates where game_id = $1 or game_template_id = $2",
+        "delete from bingo.games where id = $1",
+        "delete from bingo.game_templates where id = $2",
+        "delete from identity.users where id = any($3)",
+    ] {
+        sqlx::query(query)
+            .bind(game_id)
+            .bind(template_id)
+            .bind(user_ids)
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+    }
 }
 
-#[test]
-fn test_bingos() {
-    // invalid field amounts
-
-    assert_eq!(bingos(vec![]), 0);
-    assert_eq!(bingos(vec![false]), 0);
-    assert_eq!(bingos(vec![false, false]), 0);
-    assert_eq!(bingos(vec![false, false, false]), 0);
-    assert_eq!(bingos(vec![false, false, false, false, false]), 0);
-    assert_eq!(bingos(vec![false, false, false, false, false, false]), 0);
-    assert_eq!(
-        bingos(vec![false, false, false, false, false, false, false]),
-        0
-    );
-    assert_eq!(
-        bingos(vec![false, false, false, false, false, false, false, false]),
-        0
-    );
-
-    // 2x2
-
-    let fields = vec![
-        false, false, //
-        false, false, //
-    ];
-    assert_eq!(bingos(fields), 0);
-
-    let fields = vec![
-        true, true, //
-        false, false, //
-    ];
-    assert_eq!(bingos(fields), 1);
-
-    let fields = vec![
-        false, false, //
-        true, true, //
-    ];
-    assert_eq!(bingos(fields), 1);
-
-    let fields = vec![
-        true, false, //
-        false, true, //
-    ];
-    assert_eq!(bingos(fields), 1);
-
-    let fields = vec![
-        false, true, //
-        true, false, //
-    ];
-    assert_eq!(bingos(fields), 1);
-
-    let fields = vec![
-        true, false, //
-        true, false, //
-    ];
-    assert_eq!(bingos(fields), 1);
-
-    let fields = vec![
-        false, true, //
-        false, true, //
-    ];
-    assert_eq!(bingos(fields), 1);
-
-    let fields = vec![
-        true, true, //
-        true, true, //
-    ];
-    assert_eq!(bingos(fields), 6);
-
-    // 3x3
-    let fields = vec![
-        true, true, true, //
-        true, true, true, //
-        true, true, true, //
-    ];
-    assert_eq!(bingos(fields), 8);
-
-    // 4x4
-    let fields = vec![
-        true, true, true, true, //
-        true, true, true, true, //
-        true, true, true, true, //
-        true, true, true, true, //
-    ];
-    assert_eq!(bingos(fields), 10);
-
-    // 5x5
-
-    let fields = vec![
-        true, true, true, true, true, //
-        true, true, true, true, true, //
-        true, true, true, true, true, //
-        true, true, true, true, true, //
-        true, true, true, true, true, //
-    ];
-    assert_eq!(bingos(fields), 12);
-
-    let fields = vec![
-        true, true, true, true, true, //
-        true, true, true, true, true, //
-        true, true, true, true, true, //
-        true, false, true, true, true, //
-        true, true, true, true, true, //
-    ];
-    assert_eq!(bingos(fields), 9);
-
-    let fields = vec![
-        true, true, true, true, true, //
-        true, true, true, true, true, //
-        true, true, false, true, true, //
-        true, true, true, true, true, //
-        true, true, true, true, true, //
-    ];
-    assert_eq!(bingos(fields), 8);
-
-    // 6 x 6
-
-    let fields = vec![
-        true, true, true, true, true, true, //
-        true, true, true, true, true, true, //
-        true, true, true, true, true, true, //
-        true, true, true, true, true, true, //
-        true, true, true, true, true, true, //
-        true, true, true, true, true, true, //
-    ];
-    assert_eq!(bingos(fields), 14);
+#[cfg(test)]
+async fn test_user(conn: &mut sqlx::PgConnection) -> uuid::Uuid {
+    sqlx::query_scalar::<_, uuid::Uuid>("insert into identity.users default values returning id")
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap()
 }
@@ -1,86 +1,600 @@
 use crate::{
-    body::{PlayerOut, UsernameIn},
-    error::Result,
+    body::{BoardVisibility, PlayerOut, TeamIn, TeamStandingOut, UsernameIn, UsernameOut},
+    error::{Error, Result},
+    handler::game::{
+        field::counts_as_hit, result::assign_ranks, websocket::PRESENCE_TIMEOUT_SECONDS,
+    },
     server::{AppState, Identity},
 };
+use api::pattern::{bingos, WinPatternKind};
 use axum::{
     extract::{Extension, Path},
     Json,
 };
-use sqlx::PgConnection;
+use sqlx::{types::Json as PgJson, PgConnection};
+use unicode_categories::UnicodeCategories;
+use unicode_normalization::UnicodeNormalization;
 use uuid::Uuid;
 
+/// Names are unique within a game, taken ones get a number appended.
 pub async fn handle_update_username(
     identity: Identity,
-    Json(username): Json<UsernameIn>,
+    Json(payload): Json<UsernameIn>,
     Path(game_id): Path<Uuid>,
     Extension(state): Extension<AppState>,
-) -> Result<()> {
-    let pool = state.pool;
+) -> Result<Json<UsernameOut>> {
+    let mut transaction = state.pool.begin().await?;
     let user_id = identity.user_id;
 
+    let username = validate_username(&payload.username, &username_blocklist())?;
+
+    // lock the game so that two players can not pick the same name at once,
+    // even while nobody else has joined yet
+
+    sqlx::query!(
+        "select id from bingo.games where id = $1 for update",
+        game_id,
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    let taken = sqlx::query!(
+        "select username from bingo.players where game_id = $1 and user_id <> $2",
+        game_id,
+        user_id,
+    )
+    .fetch_all(&mut transaction)
+    .await?
+    .into_iter()
+    .map(|v| v.username)
+    .collect::<Vec<String>>();
+
+    let username = unique_username(&username, &taken);
+
+    sqlx::query!(
+        r#"
+            update bingo.players set username = $1
+            where user_id = $2 and game_id = $3
+            returning "user_id"
+        "#,
+        username,
+        user_id,
+        game_id
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    // names chosen in a round of a room stick for the next rounds
+
     sqlx::query!(
-        "update bingo.players set username = $1 where user_id = $2 and game_id = $3",
-        username.username,
+        r#"
+            update bingo.room_members m
+            set "username" = $1
+            from bingo.games g
+            where g.id = $3 and m.room_id = g.room_id and m.user_id = $2
+        "#,
+        username,
         user_id,
         game_id
     )
-    .execute(&pool)
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(UsernameOut { username }))
+}
+
+/// Comma separated words that must not be part of usernames, e.g. `USERNAME_BLOCKLIST=foo,bar`.
+fn username_blocklist() -> Vec<String> {
+    dotenv::var("USERNAME_BLOCKLIST")
+        .unwrap_or_default()
+        .split(',')
+        .map(|v| v.trim().to_lowercase())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+/// Name of a player joining the game. Players keep the name they had, e.g. before a re-deal,
+/// or the one they use in the game's room. Names are unique within the game,
+/// so the game must be locked.
+pub async fn joining_username(
+    game_id: Uuid,
+    user_id: Uuid,
+    conn: &mut PgConnection,
+) -> Result<String> {
+    let player = sqlx::query!(
+        r#"
+            select coalesce(
+                (select p.username from bingo.players p where p.game_id = $1 and p.user_id = $2),
+                (
+                    select m.username
+                    from
+                        bingo.games g
+                        join bingo.room_members m on m.room_id = g.room_id
+                    where g.id = $1 and m.user_id = $2
+                )
+            ) as username
+        "#,
+        game_id,
+        user_id,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    // names from before the validation was added may not be valid anymore
+
+    let username = player
+        .username
+        .and_then(|v| validate_username(&v, &username_blocklist()).ok())
+        .unwrap_or_else(|| "Anonymous player".to_string());
+
+    let taken = sqlx::query!(
+        r#"select "username" from bingo.players where game_id = $1 and "user_id" <> $2"#,
+        game_id,
+        user_id,
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|v| v.username)
+    .collect::<Vec<String>>();
+
+    Ok(unique_username(&username, &taken))
+}
+
+/// Normalizes a username and checks its length, characters and the blocklist.
+fn validate_username(username: &str, blocklist: &[String]) -> Result<String> {
+    let username = username.nfc().collect::<String>().trim().to_string();
+
+    // format characters are invisible, e.g. zero-width spaces or right-to-left overrides
+
+    if username
+        .chars()
+        .any(|v| v.is_control() || v.is_other_format())
+    {
+        return Err(Error::BadRequest(
+            "Usernames must not contain control or format characters.".to_string(),
+        ));
+    }
+
+    if username.is_empty() || username.chars().count() > 24 {
+        return Err(Error::BadRequest(
+            "Usernames must have at least one and at most 24 characters.".to_string(),
+        ));
+    }
+
+    let lowercase = username.to_lowercase();
+    if blocklist.iter().any(|v| lowercase.contains(v.as_str())) {
+        return Err(Error::BadRequest(
+            "This username is not allowed.".to_string(),
+        ));
+    }
+
+    Ok(username)
+}
+
+/// Appends the lowest free number to names that are taken, ignoring case.
+/// Names are shortened to keep room for the number.
+fn unique_username(username: &str, taken: &[String]) -> String {
+    let is_taken = |name: &str| {
+        taken
+            .iter()
+            .any(|v| v.to_lowercase() == name.to_lowercase())
+    };
+
+    if !is_taken(username) {
+        return username.to_string();
+    }
+
+    (2..)
+        .map(|i| {
+            let suffix = format!(" {i}");
+            let name = username.chars().take(24 - suffix.len()).collect::<String>();
+            format!("{}{suffix}", name.trim_end())
+        })
+        .find(|v| !is_taken(v))
+        .unwrap_or_else(|| username.to_string())
+}
+
+/// Switches the player to another team of the game.
+pub async fn handle_update_team(
+    identity: Identity,
+    Json(payload): Json<TeamIn>,
+    Path(game_id): Path<Uuid>,
+    Extension(state): Extension<AppState>,
+) -> Result<()> {
+    let mut conn = state.pool.acquire().await?;
+
+    set_team(game_id, identity.user_id, payload.team_id, &mut conn).await
+}
+
+/// Moves a player of a running game to one of its teams.
+pub async fn set_team(
+    game_id: Uuid,
+    user_id: Uuid,
+    team_id: Uuid,
+    conn: &mut PgConnection,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+            update bingo.players p
+            set team_id = t.id
+            from bingo.teams t, bingo.games g
+            where
+                p.game_id = $1
+                and p.user_id = $2
+                and t.id = $3
+                and t.game_id = p.game_id
+                and g.id = p.game_id
+                and g.closed = false
+            returning p.team_id
+        "#,
+        game_id,
+        user_id,
+        team_id,
+    )
+    .fetch_one(&mut *conn)
     .await?;
 
     Ok(())
 }
 
+/// Standings of all players. Their boards are only included as far as the game allows,
+/// except for the user's own board.
 pub async fn ger_players(
     game_id: Uuid,
     user_id: Uuid,
     conn: &mut PgConnection,
 ) -> Result<Vec<PlayerOut>> {
+    let game = sqlx::query!(
+        r#"
+            select
+                grid_width,
+                grid_height,
+                win_patterns as "win_patterns: PgJson<Vec<WinPatternKind>>",
+                board_visibility as "board_visibility: BoardVisibility",
+                required_confirmations is not null as "verified!"
+            from bingo.games
+            where id = $1
+        "#,
+        game_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
     let mut players = sqlx::query!(
         r#"
             select
                 p.user_id as user_id,
                 p.username as "username",
-                array_agg(f.checked order by f.position asc) as hits
+                p.team_id,
+                exists (
+                    select from bingo.connections c
+                    where
+                        c.game_id = $1
+                        and c.user_id = p.user_id
+                        and c.seen_at > now() - make_interval(secs => $2)
+                ) as "online!",
+                coalesce(
+                    (
+                        select max(c.seen_at) from bingo.connections c
+                        where c.game_id = $1 and c.user_id = p.user_id
+                    ),
+                    p.last_seen_at
+                ) as last_seen_at,
+                array_agg(f.checked order by f.position asc) as "checked!: Vec<bool>",
+                array_agg(f.confirmed order by f.position asc) as "confirmed!: Vec<bool>",
+                array_agg(f.free order by f.position asc) as "free!: Vec<bool>",
+                array_agg(
+                    coalesce(ft.caption, g.free_cell_caption) order by f.position asc
+                ) as "captions!: Vec<String>"
             from 
                 bingo.players as p
             join bingo.fields as f on f.user_id = p.user_id
-            join bingo.field_templates as ft on f.field_template_id = ft.id
+            join bingo.games as g on g.id = p.game_id
+            left join bingo.field_templates as ft on ft.id = f.field_template_id
             where 
                 p.game_id = $1 
                 and f.game_id = $1
             group by 
                 p.user_id, 
-                p.username
+                p.username,
+                p.team_id,
+                p.last_seen_at
             order by 
                 array_agg(f.checked) desc, 
                 "username" desc
         "#,
         game_id,
+        PRESENCE_TIMEOUT_SECONDS,
     )
     .fetch_all(&mut *conn)
     .await?
     .into_iter()
-    .map(|v| PlayerOut {
-        user_id: v.user_id,
-        username: v.username,
-        bingos: super::bingos(v.hits.clone().unwrap_or_default()),
-        hits: v.hits.unwrap_or_default(),
-        is_me: v.user_id == user_id,
+    .map(|v| {
+        let hits = v
+            .checked
+            .iter()
+            .zip(&v.confirmed)
+            .zip(&v.free)
+            .map(|((checked, confirmed), free)| {
+                counts_as_hit(*checked, *confirmed, *free, game.verified)
+            })
+            .collect::<Vec<bool>>();
+
+        PlayerOut {
+            user_id: v.user_id,
+            username: v.username,
+            bingos: bingos(
+                &hits,
+                game.grid_width as usize,
+                game.grid_height as usize,
+                &game.win_patterns,
+            ),
+            hit_amount: hits.iter().filter(|v| **v).count() as i32,
+            hits,
+            captions: v.captions,
+            is_me: v.user_id == user_id,
+            team_id: v.team_id,
+            online: v.online,
+            last_seen_at: v.last_seen_at,
+        }
     })
     .collect::<Vec<PlayerOut>>();
 
     players.sort_by(|a, b| {
-        let a_hits = a.hits.iter().filter(|v| **v).count();
-
-        let b_hits = b.hits.iter().filter(|v| **v).count();
-
         if a.bingos == b.bingos {
-            b_hits.partial_cmp(&a_hits).unwrap()
+            b.hit_amount.partial_cmp(&a.hit_amount).unwrap()
         } else {
             b.bingos.partial_cmp(&a.bingos).unwrap()
         }
     });
 
+    hide_boards(&mut players, game.board_visibility);
+
     Ok(players)
 }
+
+/// Removes what the game does not allow to see of the other players' boards.
+fn hide_boards(players: &mut [PlayerOut], visibility: BoardVisibility) {
+    for player in players.iter_mut().filter(|v| !v.is_me) {
+        match visibility {
+            BoardVisibility::Hidden => {
+                player.hits.clear();
+                player.captions.clear();
+            }
+            BoardVisibility::Hits => player.captions.clear(),
+            BoardVisibility::Full => {}
+        }
+    }
+}
+
+/// Standings of the game's teams, given its players. Empty for games without teams.
+pub async fn list_teams(
+    game_id: Uuid,
+    players: &[PlayerOut],
+    conn: &mut PgConnection,
+) -> Result<Vec<TeamStandingOut>> {
+    let mut teams = sqlx::query!(
+        "select id, name from bingo.teams where game_id = $1 order by position",
+        game_id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|v| TeamStandingOut {
+        id: v.id,
+        name: v.name,
+        player_amount: 0,
+        bingos: 0,
+        hits: 0,
+        rank: 0,
+        is_mine: false,
+    })
+    .collect::<Vec<TeamStandingOut>>();
+
+    rank_teams(&mut teams, players);
+
+    Ok(teams)
+}
+
+/// Sums up bingos and hits of each team's players and sorts teams by them.
+/// Tied teams share a rank.
+fn rank_teams(teams: &mut [TeamStandingOut], players: &[PlayerOut]) {
+    for player in players {
+        if let Some(team) = teams.iter_mut().find(|v| Some(v.id) == player.team_id) {
+            team.player_amount += 1;
+            team.bingos += player.bingos;
+            team.hits += player.hit_amount;
+            team.is_mine |= player.is_me;
+        }
+    }
+
+    teams.sort_by(|a, b| b.bingos.cmp(&a.bingos).then(b.hits.cmp(&a.hits)));
+
+    assign_ranks(teams, |v| (v.bingos, v.hits), |v, rank| v.rank = rank);
+}
+
+#[test]
+fn test_rank_teams() {
+    let team = |name: &str| TeamStandingOut {
+        id: Uuid::new_v4(),
+        name: name.to_string(),
+        player_amount: 0,
+        bingos: 0,
+        hits: 0,
+        rank: 0,
+        is_mine: false,
+    };
+    let player = |team: &TeamStandingOut, bingos: i32, hits: i32, is_me: bool| PlayerOut {
+        user_id: Uuid::new_v4(),
+        username: "Anonymous player".to_string(),
+        bingos,
+        hits: Vec::new(),
+        hit_amount: hits,
+        captions: Vec::new(),
+        is_me,
+        team_id: Some(team.id),
+        online: true,
+        last_seen_at: None,
+    };
+
+    let mut teams = vec![team("red"), team("blue"), team("green")];
+    let players = vec![
+        player(&teams[0], 1, 3, false),
+        player(&teams[0], 0, 2, false),
+        player(&teams[1], 1, 5, true),
+        player(&teams[2], 0, 4, false),
+        PlayerOut {
+            team_id: None,
+            ..player(&teams[2], 3, 9, false)
+        },
+    ];
+    rank_teams(&mut teams, &players);
+
+    assert_eq!(
+        teams
+            .iter()
+            .map(|v| (
+                v.name.as_str(),
+                v.player_amount,
+                v.bingos,
+                v.hits,
+                v.is_mine
+            ))
+            .collect::<Vec<_>>(),
+        vec![
+            ("red", 2, 1, 5, false),
+            ("blue", 1, 1, 5, true),
+            ("green", 1, 0, 4, false),
+        ]
+    );
+}
+
+#[test]
+fn test_hide_boards() {
+    let player = |is_me: bool| PlayerOut {
+        user_id: Uuid::new_v4(),
+        username: "Anonymous player".to_string(),
+        bingos: 0,
+        hits: vec![true, false, false, true],
+        hit_amount: 2,
+        captions: ["a", "b", "c", "d"].map(String::from).to_vec(),
+        is_me,
+        team_id: None,
+        online: true,
+        last_seen_at: None,
+    };
+    let visible = |visibility: BoardVisibility| {
+        let mut players = vec![player(true), player(false)];
+        hide_boards(&mut players, visibility);
+        players
+            .iter()
+            .map(|v| (v.hits.len(), v.hit_amount, v.captions.len()))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(visible(BoardVisibility::Hidden), vec![(4, 2, 4), (0, 2, 0)]);
+    assert_eq!(visible(BoardVisibility::Hits), vec![(4, 2, 4), (4, 2, 0)]);
+    assert_eq!(visible(BoardVisibility::Full), vec![(4, 2, 4), (4, 2, 4)]);
+}
+
+#[test]
+fn test_validate_username() {
+    let blocklist = vec!["heck".to_string()];
+
+    assert_eq!(
+        validate_username("  Cafe\u{301} ", &blocklist).unwrap(),
+        "Caf\u{e9}"
+    );
+    assert_eq!(
+        validate_username(&"ü".repeat(24), &blocklist)
+            .unwrap()
+            .chars()
+            .count(),
+        24
+    );
+    assert!(validate_username("   ", &blocklist).is_err());
+    assert!(validate_username(&"a".repeat(25), &blocklist).is_err());
+    assert!(validate_username("bob\u{7}", &blocklist).is_err());
+    assert!(validate_username("a\nb", &blocklist).is_err());
+    assert!(validate_username("evil\u{202e}gnp.exe", &blocklist).is_err());
+    assert!(validate_username("a\u{200d}b", &blocklist).is_err());
+    assert!(validate_username("\u{200b}", &blocklist).is_err());
+    assert!(validate_username("Bob\u{200b}", &blocklist).is_err());
+    assert!(validate_username("What the HECK", &blocklist).is_err());
+    assert!(validate_username("What the HECK", &[]).is_ok());
+}
+
+#[test]
+fn test_unique_username() {
+    let taken = ["Bob", "bob 2", "Alice"].map(String::from);
+
+    assert_eq!(unique_username("Carol", &taken), "Carol");
+    assert_eq!(unique_username("alice", &taken), "alice 2");
+    assert_eq!(unique_username("BOB", &taken), "BOB 3");
+    assert_eq!(
+        unique_username(
+            "Alice with a longer name",
+            &[String::from("alice with a longer name")]
+        ),
+        "Alice with a longer na 2"
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs a database, set DATABASE_URL"]
+async fn test_joining_username() {
+    use sqlx::Connection;
+
+    let mut conn = super::test_connection().await;
+    let mut transaction = conn.begin().await.unwrap();
+
+    let (game_id, host_id) = super::test_game(serde_json::json!({}), &mut transaction).await;
+    super::test_player(game_id, &mut transaction).await;
+    super::test_player(game_id, &mut transaction).await;
+
+    let mut usernames = ger_players(game_id, host_id, &mut transaction)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|v| v.username)
+        .collect::<Vec<String>>();
+    usernames.sort();
+    assert_eq!(
+        usernames,
+        vec![
+            "Anonymous player",
+            "Anonymous player 2",
+            "Anonymous player 3"
+        ]
+    );
+
+    // players keep their name when they are dealt a new card, unless it is invalid
+
+    let rename = |username: &'static str| {
+        sqlx::query("update bingo.players set username = $1 where user_id = $2")
+            .bind(username)
+            .bind(host_id)
+    };
+
+    rename("Bob").execute(&mut transaction).await.unwrap();
+    assert_eq!(
+        joining_username(game_id, host_id, &mut transaction)
+            .await
+            .unwrap(),
+        "Bob"
+    );
+
+    rename("Bob\u{200b}")
+        .execute(&mut transaction)
+        .await
+        .unwrap();
+    assert_eq!(
+        joining_username(game_id, host_id, &mut transaction)
+            .await
+            .unwrap(),
+        "Anonymous player"
+    );
+}
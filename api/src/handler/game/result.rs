@@ -0,0 +1,408 @@
+use crate::{
+    body::{GameResultsOut, PastGameOut, StandingOut},
+    error::Result,
+    handler::game::player::ger_players,
+    server::{AppState, Identity},
+};
+use axum::{
+    extract::{Extension, Path},
+    Json,
+};
+use chrono::Utc;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+pub async fn handle_finish_game(
+    identity: Identity,
+    Path(game_id): Path<Uuid>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<GameResultsOut>> {
+    let mut transaction = state.pool.begin().await?;
+    let user_id = identity.user_id;
+
+    // only the host can finish a game
+
+    let game = sqlx::query!(
+        r#"
+            select id from bingo.games
+            where id = $1 and created_by = $2 and closed = false
+        "#,
+        game_id,
+        user_id,
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    close_game(game.id, &mut transaction).await?;
+
+    let results = game_results(game.id, user_id, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(results))
+}
+
+pub async fn handle_game_results(
+    identity: Identity,
+    Path(game_id): Path<Uuid>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<GameResultsOut>> {
+    let mut conn = state.pool.acquire().await?;
+
+    let results = game_results(game_id, identity.user_id, &mut conn).await?;
+
+    Ok(Json(results))
+}
+
+pub async fn handle_list_past_games(
+    identity: Identity,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<Vec<PastGameOut>>> {
+    let pool = &state.pool;
+
+    let games = sqlx::query!(
+        r#"
+            select
+                sq.game_id,
+                sq.title,
+                sq.closed,
+                sq.created_at,
+                sq.closed_at,
+                sq.player_amount,
+                sq.bingos,
+                sq.hits,
+                sq.rank
+            from
+                (
+                    select
+                        r.game_id,
+                        r.user_id,
+                        r.bingos,
+                        r.hits,
+                        gt.title,
+                        g.closed,
+                        g.created_at,
+                        g.closed_at,
+                        count(r.user_id) over (partition by r.game_id) player_amount,
+                        rank() over (
+                            partition by r.game_id
+                            order by r.bingos desc, r.hits desc
+                        ) rank
+                    from
+                        bingo.results r
+                        join bingo.games g on g.id = r.game_id
+                        join bingo.game_templates gt on gt.id = g.game_template_id
+                ) sq
+            where
+                sq.user_id = $1
+            order by
+                coalesce(sq.closed_at, sq.created_at) desc
+        "#,
+        identity.user_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|v| {
+        let rank = v.rank.unwrap_or(0);
+
+        PastGameOut {
+            id: v.game_id,
+            title: v.title,
+            finished: v.closed,
+            started_at: v.created_at,
+            finished_at: v.closed_at,
+            player_amount: v.player_amount.unwrap_or(0),
+            bingos: v.bingos,
+            hits: v.hits,
+            rank,
+            winner: v.closed && rank == 1,
+        }
+    })
+    .collect::<Vec<PastGameOut>>();
+
+    Ok(Json(games))
+}
+
+/// Archives the standings of all players and closes the game,
+/// which tells the sockets of its players to stop. Timed games end at their deadline at the latest.
+pub async fn close_game(game_id: Uuid, conn: &mut PgConnection) -> Result<()> {
+    archive_players(game_id, None, &mut *conn).await?;
+
+    sqlx::query!(
+        "update bingo.games set closed = true, closed_at = least(now(), ends_at) where id = $1",
+        game_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Writes the current standings of a game's players (or of a single player) to `bingo.results`,
+/// so they survive the player leaving or the game being closed.
+pub async fn archive_players(
+    game_id: Uuid,
+    user_id: Option<Uuid>,
+    conn: &mut PgConnection,
+) -> Result<()> {
+    let standings = ger_players(game_id, user_id.unwrap_or_else(Uuid::nil), &mut *conn)
+        .await?
+        .into_iter()
+        .filter(|v| user_id.map(|user_id| user_id == v.user_id).unwrap_or(true))
+        .collect::<Vec<_>>();
+
+    let user_ids = standings.iter().map(|v| v.user_id).collect::<Vec<Uuid>>();
+    let bingos = standings.iter().map(|v| v.bingos).collect::<Vec<i32>>();
+    let hits = standings.iter().map(|v| v.hit_amount).collect::<Vec<i32>>();
+
+    sqlx::query!(
+        r#"
+            insert into bingo.results (game_id, "user_id", "username", bingos, hits, joined_at)
+            select
+                p.game_id,
+                p.user_id,
+                p.username,
+                s.bingos,
+                s.hits,
+                p.joined_at
+            from
+                bingo.players p
+                join unnest($2::uuid[], $3::integer[], $4::integer[]) as s(user_id, bingos, hits)
+                    on s.user_id = p.user_id
+            where
+                p.game_id = $1
+            on conflict (game_id, "user_id") do update
+            set
+                "username" = excluded.username,
+                bingos = excluded.bingos,
+                hits = excluded.hits,
+                joined_at = excluded.joined_at,
+                left_at = now()
+        "#,
+        game_id,
+        &user_ids,
+        &bingos,
+        &hits,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Collects archived and, for games still running, live standings.
+/// Only the host and (former) players may see them.
+pub async fn game_results(
+    game_id: Uuid,
+    user_id: Uuid,
+    conn: &mut PgConnection,
+) -> Result<GameResultsOut> {
+    let game = sqlx::query!(
+        r#"
+            select
+                g.id,
+                gt.title,
+                g.closed,
+                g.created_at,
+                g.closed_at
+            from
+                bingo.games g
+                join bingo.game_templates gt on gt.id = g.game_template_id
+            where
+                g.id = $1
+                and (
+                    g.created_by = $2
+                    or exists (select from bingo.players p where p.game_id = g.id and p.user_id = $2)
+                    or exists (select from bingo.results r where r.game_id = g.id and r.user_id = $2)
+                )
+        "#,
+        game_id,
+        user_id,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let now = Utc::now();
+
+    let mut standings = standings(game.id, game.closed, user_id, &mut *conn).await?;
+
+    rank_standings(&mut standings, game.closed);
+
+    Ok(GameResultsOut {
+        id: game.id,
+        title: game.title,
+        finished: game.closed,
+        started_at: game.created_at,
+        finished_at: game.closed_at,
+        duration_seconds: (game.closed_at.unwrap_or(now) - game.created_at).num_seconds(),
+        standings,
+    })
+}
+
+/// Unranked standings of a game: archived ones and, for games still running, live ones.
+pub async fn standings(
+    game_id: Uuid,
+    closed: bool,
+    user_id: Uuid,
+    conn: &mut PgConnection,
+) -> Result<Vec<StandingOut>> {
+    let now = Utc::now();
+
+    let mut standings = sqlx::query!(
+        r#"
+            select "user_id", "username", bingos, hits, joined_at, left_at
+            from bingo.results
+            where game_id = $1
+        "#,
+        game_id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|v| StandingOut {
+        user_id: v.user_id,
+        username: v.username,
+        bingos: v.bingos,
+        hits: v.hits,
+        rank: 0,
+        winner: false,
+        active: false,
+        duration_seconds: (v.left_at - v.joined_at).num_seconds(),
+        is_me: v.user_id == user_id,
+    })
+    .collect::<Vec<StandingOut>>();
+
+    if !closed {
+        let joined_at = sqlx::query!(
+            r#"select "user_id", joined_at from bingo.players where game_id = $1"#,
+            game_id
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        for player in ger_players(game_id, user_id, &mut *conn).await? {
+            let duration_seconds = joined_at
+                .iter()
+                .find(|v| v.user_id == player.user_id)
+                .map(|v| (now - v.joined_at).num_seconds())
+                .unwrap_or(0);
+
+            standings.retain(|v| v.user_id != player.user_id);
+            standings.push(StandingOut {
+                user_id: player.user_id,
+                username: player.username,
+                bingos: player.bingos,
+                hits: player.hit_amount,
+                rank: 0,
+                winner: false,
+                active: true,
+                duration_seconds,
+                is_me: player.is_me,
+            });
+        }
+    }
+
+    Ok(standings)
+}
+
+/// Sorts standings by bingos and hits. Tied players share a rank,
+/// winners are only declared once the game is finished.
+pub fn rank_standings(standings: &mut [StandingOut], finished: bool) {
+    standings.sort_by(|a, b| {
+        b.bingos
+            .cmp(&a.bingos)
+            .then(b.hits.cmp(&a.hits))
+            .then(a.username.cmp(&b.username))
+    });
+
+    assign_ranks(
+        standings,
+        |v| (v.bingos, v.hits),
+        |v, rank| {
+            v.rank = rank;
+            v.winner = finished && rank == 1;
+        },
+    );
+}
+
+/// Ranks entries in their current order. Entries with the same key share a rank
+/// and the following ones skip as many ranks, e.g. 1, 2, 2, 4.
+pub fn assign_ranks<T, K: PartialEq>(
+    entries: &mut [T],
+    key: impl Fn(&T) -> K,
+    mut set_rank: impl FnMut(&mut T, u32),
+) {
+    let mut rank = 0;
+    let mut previous = None;
+    for (i, entry) in entries.iter_mut().enumerate() {
+        let current = key(entry);
+        if previous.as_ref() != Some(&current) {
+            rank = i as u32 + 1;
+            previous = Some(current);
+        }
+        set_rank(entry, rank);
+    }
+}
+
+#[test]
+fn test_rank_standings() {
+    let standing = |username: &str, bingos: i32, hits: i32| StandingOut {
+        user_id: Uuid::new_v4(),
+        username: username.to_string(),
+        bingos,
+        hits,
+        rank: 0,
+        winner: false,
+        active: false,
+        duration_seconds: 0,
+        is_me: false,
+    };
+
+    let mut standings = vec![
+        standing("a", 0, 3),
+        standing("b", 1, 5),
+        standing("c", 1, 5),
+        standing("d", 2, 5),
+    ];
+
+    rank_standings(&mut standings, false);
+    assert_eq!(
+        standings
+            .iter()
+            .map(|v| (v.username.as_str(), v.rank))
+            .collect::<Vec<_>>(),
+        vec![("d", 1), ("b", 2), ("c", 2), ("a", 4)]
+    );
+    assert!(standings.iter().all(|v| !v.winner));
+
+    rank_standings(&mut standings, true);
+    assert_eq!(
+        standings.iter().filter(|v| v.winner).count(),
+        1,
+        "only the leading player wins a finished game"
+    );
+    assert!(standings[0].winner);
+
+    let mut standings = vec![standing("a", 1, 4), standing("b", 1, 4)];
+    rank_standings(&mut standings, true);
+    assert!(standings.iter().all(|v| v.winner && v.rank == 1));
+}
+
+#[test]
+fn test_assign_ranks() {
+    let ranks = |keys: &[(i32, i32)]| {
+        let mut entries = keys.iter().map(|v| (*v, 0)).collect::<Vec<_>>();
+        assign_ranks(&mut entries, |v| v.0, |v, rank| v.1 = rank);
+        entries.into_iter().map(|v| v.1).collect::<Vec<u32>>()
+    };
+
+    assert_eq!(ranks(&[]), Vec::<u32>::new());
+    assert_eq!(ranks(&[(2, 5)]), vec![1]);
+    assert_eq!(ranks(&[(2, 5), (1, 5), (1, 5), (0, 3)]), vec![1, 2, 2, 4]);
+    assert_eq!(ranks(&[(1, 4), (1, 4), (1, 4)]), vec![1, 1, 1]);
+
+    // the whole key decides, not just its first part
+
+    assert_eq!(ranks(&[(1, 5), (1, 4), (1, 4)]), vec![1, 2, 2]);
+}
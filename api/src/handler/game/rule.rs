@@ -0,0 +1,71 @@
+use crate::body::CaptionRule;
+
+/// Categories every row must contain, over all rules.
+pub fn row_categories(rules: &[CaptionRule]) -> Vec<&str> {
+    let mut categories = Vec::new();
+
+    for rule in rules {
+        if let CaptionRule::EachPerRow { categories: row } = rule {
+            for category in row {
+                if !categories.contains(&category.as_str()) {
+                    categories.push(category.as_str());
+                }
+            }
+        }
+    }
+
+    categories
+}
+
+/// How many captions of a category a card needs at least, given its amount of rows.
+pub fn min_per_card(rules: &[CaptionRule], category: &str, rows: usize) -> usize {
+    rules
+        .iter()
+        .map(|rule| match rule {
+            CaptionRule::MinPerCard { category: v, min } if v == category => *min,
+            CaptionRule::EachPerRow { categories } if categories.iter().any(|v| v == category) => {
+                rows
+            }
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// How many captions of a category a card may have at most.
+pub fn max_per_card(rules: &[CaptionRule], category: &str) -> Option<usize> {
+    rules
+        .iter()
+        .filter_map(|rule| match rule {
+            CaptionRule::MaxPerCard { category: v, max } if v == category => Some(*max),
+            _ => None,
+        })
+        .min()
+}
+
+#[test]
+fn test_rules_per_card() {
+    let max = |category: &str, max| CaptionRule::MaxPerCard {
+        category: category.to_string(),
+        max,
+    };
+    let row = |categories: &[&str]| CaptionRule::EachPerRow {
+        categories: categories.iter().map(|v| v.to_string()).collect(),
+    };
+
+    let rules = [
+        max("rare", 2),
+        max("rare", 3),
+        row(&["common"]),
+        CaptionRule::MinPerCard {
+            category: "common".to_string(),
+            min: 7,
+        },
+    ];
+    assert_eq!(max_per_card(&rules, "rare"), Some(2));
+    assert_eq!(max_per_card(&rules, "common"), None);
+    assert_eq!(min_per_card(&rules, "common", 5), 7);
+    assert_eq!(min_per_card(&rules, "common", 9), 9);
+    assert_eq!(min_per_card(&rules, "rare", 5), 0);
+    assert_eq!(row_categories(&rules), vec!["common"]);
+}
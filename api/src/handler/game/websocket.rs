@@ -1,17 +1,25 @@
 use crate::{
-    body::MessageOut,
+    body::{Announcers, MessageOut, SpectateIn},
     error::Result,
-    handler::game::{field::list_fields, player::ger_players},
+    handler::game::{
+        call::list_calls,
+        chat::{latest_revision, list_messages},
+        field::{list_boards, list_fields, pending_checks},
+        player::{ger_players, list_teams},
+    },
+    pg_listen::Topic,
     server::{AppState, Identity},
 };
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        Extension, Path, TypedHeader,
+        Extension, Path, Query, TypedHeader,
     },
     response::IntoResponse,
 };
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{Acquire, Postgres};
+use tokio::time::{interval, Instant, Interval};
 use uuid::Uuid;
 
 pub async fn ws(
@@ -22,17 +30,125 @@ pub async fn ws(
     Extension(state): Extension<AppState>,
 ) -> impl IntoResponse {
     ws.on_upgrade(move |socket| async move {
-        handle_socket(socket, &state, identity.user_id, game_id).await
+        handle_socket(socket, &state, identity.user_id, game_id, Viewer::Player).await
+    })
+}
+
+/// Socket of spectators, who get the standings and optionally all boards, but no card.
+pub async fn spectate(
+    ws: WebSocketUpgrade,
+    identity: Identity,
+    Path(game_id): Path<Uuid>,
+    Query(options): Query<SpectateIn>,
+    Extension(state): Extension<AppState>,
+) -> impl IntoResponse {
+    let viewer = Viewer::Spectator {
+        boards: options.boards,
+    };
+
+    ws.on_upgrade(move |socket| async move {
+        handle_socket(socket, &state, identity.user_id, game_id, viewer).await
     })
 }
 
-async fn handle_socket(socket: WebSocket, state: &AppState, user_id: Uuid, game_id: Uuid) {
-    if let Err(err) = send_game_update_messages(socket, state, user_id, game_id).await {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Viewer {
+    Player,
+    Spectator { boards: bool },
+}
+
+/// Sockets renew their connection this often.
+const HEARTBEAT_SECONDS: u64 = 20;
+
+/// Connections without a heartbeat for this long do not count anymore.
+pub const PRESENCE_TIMEOUT_SECONDS: f64 = 60.0;
+
+async fn handle_socket(
+    socket: WebSocket,
+    state: &AppState,
+    user_id: Uuid,
+    game_id: Uuid,
+    viewer: Viewer,
+) {
+    // presence is only tracked for players, changes are broadcast through the connections trigger
+
+    let connection_id = (viewer == Viewer::Player).then(Uuid::new_v4);
+
+    if let Err(err) =
+        send_game_update_messages(socket, state, user_id, game_id, viewer, connection_id).await
+    {
         // if let Err(err) = socket.close().await {
         //     tracing::error!("closing socket failes: {:?}", err);
         // }
         tracing::error!("sending game update messgages failes: {:?}", err);
     }
+
+    if let Some(connection_id) = connection_id {
+        if let Err(err) = close_connection(connection_id, &state.pool).await {
+            tracing::error!("closing connection failes: {:?}", err);
+        }
+    }
+}
+
+/// Registers the socket or renews its heartbeat. Connections of the game that expired
+/// in the meantime are removed, which lets the other sockets know.
+async fn renew_connection(
+    connection_id: Uuid,
+    game_id: Uuid,
+    user_id: Uuid,
+    conn: impl Acquire<'_, Database = Postgres>,
+) -> Result<()> {
+    let mut conn = conn.acquire().await?;
+
+    sqlx::query!(
+        r#"
+            insert into bingo.connections (id, game_id, "user_id") values ($1, $2, $3)
+            on conflict (id) do update set seen_at = now()
+        "#,
+        connection_id,
+        game_id,
+        user_id,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            delete from bingo.connections
+            where game_id = $1 and seen_at <= now() - make_interval(secs => $2)
+        "#,
+        game_id,
+        PRESENCE_TIMEOUT_SECONDS,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Removes the socket's connection, if it has been registered, and remembers when
+/// the player was seen the last time.
+async fn close_connection(
+    connection_id: Uuid,
+    conn: impl Acquire<'_, Database = Postgres>,
+) -> Result<()> {
+    let mut conn = conn.acquire().await?;
+
+    sqlx::query!(
+        r#"
+            with closed as (
+                delete from bingo.connections where id = $1 returning game_id, "user_id"
+            )
+            update bingo.players p set last_seen_at = now()
+            from closed c
+            where p.game_id = c.game_id and p.user_id = c.user_id
+        "#,
+        connection_id,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
 }
 
 pub async fn send_game_update_messages(
@@ -40,71 +156,324 @@ pub async fn send_game_update_messages(
     state: &AppState,
     user_id: Uuid,
     game_id: Uuid,
+    viewer: Viewer,
+    connection_id: Option<Uuid>,
 ) -> Result<Vec<String>> {
     let pool = &state.pool;
     let mut receiver = state.receiver.clone();
     let mut socket_healthy = true;
 
-    // check if game exists
+    // check if game exists and the user plays or watches it
 
     let game = sqlx::query!(
         r#"
             select 
                 g.id,
-                g.game_template_id
+                g.game_template_id,
+                g.ends_at
             from 
                 bingo.games g
-            inner join
-                bingo.players p on p.game_id = g.id
             where 
-                p.user_id = $1 and g.id = $2 and closed = false
+                g.id = $2
+                and closed = false
+                and (
+                    (
+                        not $3
+                        and exists (
+                            select from bingo.players p where p.game_id = g.id and p.user_id = $1
+                        )
+                    )
+                    or (
+                        $3
+                        and exists (
+                            select from bingo.spectators s
+                            where s.game_id = g.id and s.user_id = $1
+                        )
+                    )
+                )
         "#,
         user_id,
         game_id,
+        viewer != Viewer::Player,
     )
     .fetch_one(pool)
     .await?;
 
-    // while socket is healthy: listen for changes in tokio watch and send respective updates to client
+    // sockets count as connections only once they belong to a player of the game
+
+    if let Some(connection_id) = connection_id {
+        renew_connection(connection_id, game_id, user_id, pool).await?;
+    }
+
+    // while socket is healthy: listen for changes in tokio watch and send respective updates to client,
+    // and keep the countdown of timed games in sync
 
     let mut latest_game_update_at = Utc::now() - Duration::days(1);
+    let mut countdown_interval = game
+        .ends_at
+        .map(|_| interval(std::time::Duration::from_secs(10)));
+    let mut heartbeat_interval =
+        connection_id.map(|_| interval(std::time::Duration::from_secs(HEARTBEAT_SECONDS)));
+
+    // the chat history is sent once, afterwards only messages with a higher revision.
+    // The revision is read first, messages committed in between are sent twice at most.
+
+    let mut latest_chat_update_at = Utc::now() - Duration::days(1);
+    let mut conn = pool.acquire().await?;
+    let mut chat_revision = latest_revision(game_id, &mut conn).await?;
+    let history = list_messages(game_id, user_id, None, &mut conn).await?;
+    drop(conn);
+    if let Err(err) = socket
+        .send(Message::Text(serde_json::to_string(
+            &MessageOut::ChatHistory(history),
+        )?))
+        .await
+    {
+        tracing::warn!("Failed to send message: {:?}", err);
+        socket_healthy = false;
+    }
 
     while socket_healthy {
-        if receiver.changed().await.is_ok() {
-            let game_updated_recently = receiver
-                .borrow()
-                .get(&game_id)
-                .map(|v| v > &latest_game_update_at)
-                .unwrap_or(false);
-
-            if game_updated_recently {
-                latest_game_update_at = Utc::now();
-                let mut messages = Vec::new();
-
-                if game_id == game.id {
+        let mut messages = Vec::new();
+        let mut closed = false;
+
+        tokio::select! {
+            changed = receiver.changed() => {
+                if changed.is_err() {
+                    tracing::error!(
+                        "channel connecting postgres notifications listener to websocket is broken"
+                    );
+                    break;
+                }
+
+                let (game_updated_recently, chat_updated_at) = {
+                    let changes = receiver.borrow();
+                    (
+                        changes
+                            .get(&(game_id, Topic::Game))
+                            .map(|v| v > &latest_game_update_at)
+                            .unwrap_or(false),
+                        changes
+                            .get(&(game_id, Topic::Chat))
+                            .filter(|v| *v > &latest_chat_update_at)
+                            .copied(),
+                    )
+                };
+
+                if let Some(chat_updated_at) = chat_updated_at {
+                    latest_chat_update_at = chat_updated_at;
+                    let mut conn = pool.acquire().await?;
+
+                    let chat = list_messages(game_id, user_id, Some(chat_revision), &mut conn).await?;
+                    if let Some(revision) = chat.iter().map(|v| v.revision).max() {
+                        chat_revision = revision;
+                        messages.push(serde_json::to_string(&MessageOut::Chat(chat))?);
+                    }
+                }
+
+                if game_updated_recently && game_id == game.id {
+                    latest_game_update_at = Utc::now();
                     let mut conn = pool.acquire().await?;
 
-                    let fields = list_fields(game_id, user_id, &mut conn).await?;
-                    messages.push(serde_json::to_string(&MessageOut::Fields(fields))?);
+                    let game_state = sqlx::query!(
+                        r#"
+                            select
+                                id,
+                                closed,
+                                access_code,
+                                ends_at,
+                                required_confirmations,
+                                announcers as "announcers: Announcers",
+                                caller
+                            from bingo.games
+                            where id = $1
+                        "#,
+                        game_id
+                    )
+                    .fetch_one(&mut conn)
+                    .await?;
+                    messages.push(serde_json::to_string(&MessageOut::Game {
+                        id: game_state.id,
+                        open: !game_state.closed,
+                        access_code: game_state.access_code,
+                    })?);
+
+                    if viewer == Viewer::Player {
+                        let fields = list_fields(game_id, user_id, &mut conn).await?;
+                        messages.push(serde_json::to_string(&MessageOut::Fields(fields))?);
+                    }
 
                     let players = ger_players(game_id, user_id, &mut conn).await?;
+                    let teams = list_teams(game_id, &players, &mut conn).await?;
+
+                    if viewer == (Viewer::Spectator { boards: true }) {
+                        let boards = list_boards(game_id, &players, &mut conn).await?;
+                        messages.push(serde_json::to_string(&MessageOut::Boards(boards))?);
+                    }
+
                     messages.push(serde_json::to_string(&MessageOut::Players(players))?);
 
-                    for message in messages {
-                        if let Err(err) = socket.send(Message::Text(message)).await {
-                            tracing::warn!("Failed to send message: {:?}", err);
-                            socket_healthy = false;
-                        }
+                    if !teams.is_empty() {
+                        messages.push(serde_json::to_string(&MessageOut::Teams(teams))?);
                     }
+
+                    if game_state.required_confirmations.is_some() && viewer == Viewer::Player {
+                        let checks = pending_checks(game_id, user_id, &mut conn).await?;
+                        messages.push(serde_json::to_string(&MessageOut::PendingChecks(checks))?);
+                    }
+
+                    if game_state.caller || game_state.announcers != Announcers::Nobody {
+                        let calls = list_calls(game_id, &mut conn).await?;
+                        messages.push(serde_json::to_string(&MessageOut::Calls(calls))?);
+                    }
+
+                    if game_state.closed {
+                        messages.push(serde_json::to_string(&MessageOut::Ended { id: game_id })?);
+                        closed = true;
+                    } else if let Some(ends_at) = game_state.ends_at {
+                        messages.push(serde_json::to_string(&countdown(ends_at))?);
+                    }
+                }
+            }
+            // notice closed sockets right away instead of on the next update
+
+            received = socket.recv() => {
+                match received {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    _ => {}
                 }
             }
-        } else {
-            tracing::error!(
-                "channel connecting postgres notifications listener to websocket is broken"
-            );
+            // only timed games have a countdown, their deadline does not change
+
+            _ = tick(&mut countdown_interval) => {
+                if let Some(ends_at) = game.ends_at {
+                    messages.push(serde_json::to_string(&countdown(ends_at))?);
+                }
+            }
+            _ = tick(&mut heartbeat_interval) => {
+                if let Some(connection_id) = connection_id {
+                    if let Err(err) = renew_connection(connection_id, game_id, user_id, pool).await {
+                        tracing::warn!("renewing connection failes: {:?}", err);
+                    }
+                }
+            }
+        }
+
+        for message in messages {
+            if let Err(err) = socket.send(Message::Text(message)).await {
+                tracing::warn!("Failed to send message: {:?}", err);
+                socket_healthy = false;
+            }
+        }
+
+        // stop sending updates for closed games
+
+        if closed {
             break;
         }
     }
 
     Ok(vec![])
 }
+
+/// Ticks like the interval, or never without one.
+async fn tick(interval: &mut Option<Interval>) -> Instant {
+    match interval {
+        Some(interval) => interval.tick().await,
+        None => std::future::pending().await,
+    }
+}
+
+fn countdown(ends_at: DateTime<Utc>) -> MessageOut {
+    MessageOut::Countdown {
+        ends_at,
+        seconds_left: (ends_at - Utc::now()).num_seconds().max(0),
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs a database, set DATABASE_URL"]
+async fn test_presence() {
+    let mut conn = super::test_connection().await;
+    let mut transaction = conn.begin().await.unwrap();
+
+    let (game_id, host_id) = super::test_game(serde_json::json!({}), &mut transaction).await;
+    let player_id = super::test_player(game_id, &mut transaction).await;
+
+    let online = |players: Vec<crate::body::PlayerOut>| {
+        let mut online = players
+            .iter()
+            .filter(|v| v.online)
+            .map(|v| v.user_id)
+            .collect::<Vec<Uuid>>();
+        online.sort();
+        online
+    };
+
+    assert!(online(
+        ger_players(game_id, host_id, &mut transaction)
+            .await
+            .unwrap()
+    )
+    .is_empty());
+
+    // players stay online as long as one of their sockets is open
+
+    let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+    renew_connection(first, game_id, host_id, &mut transaction)
+        .await
+        .unwrap();
+    renew_connection(second, game_id, host_id, &mut transaction)
+        .await
+        .unwrap();
+    close_connection(first, &mut transaction).await.unwrap();
+    assert_eq!(
+        online(
+            ger_players(game_id, host_id, &mut transaction)
+                .await
+                .unwrap()
+        ),
+        vec![host_id]
+    );
+
+    // sockets that stop sending heartbeats expire and are cleaned up by the others
+
+    sqlx::query(
+        "update bingo.connections set seen_at = now() - interval '2 minutes' where id = $1",
+    )
+    .bind(second)
+    .execute(&mut transaction)
+    .await
+    .unwrap();
+    assert!(online(
+        ger_players(game_id, host_id, &mut transaction)
+            .await
+            .unwrap()
+    )
+    .is_empty());
+
+    renew_connection(Uuid::new_v4(), game_id, player_id, &mut transaction)
+        .await
+        .unwrap();
+    assert_eq!(
+        online(
+            ger_players(game_id, host_id, &mut transaction)
+                .await
+                .unwrap()
+        ),
+        vec![player_id]
+    );
+    assert_eq!(
+        sqlx::query_scalar::<_, i64>("select count(*) from bingo.connections where game_id = $1")
+            .bind(game_id)
+            .fetch_one(&mut transaction)
+            .await
+            .unwrap(),
+        1
+    );
+
+    let players = ger_players(game_id, host_id, &mut transaction)
+        .await
+        .unwrap();
+    assert!(players.iter().all(|v| v.last_seen_at.is_some()));
+}
@@ -1,3 +1,4 @@
 pub mod auth;
 pub mod game;
+pub mod room;
 pub mod template;
@@ -0,0 +1,369 @@
+use crate::{
+    body::{GameOut, GameSettingsIn, RoomIn, RoomMemberOut, RoomOut, RoundOut, ScoreOut},
+    error::{Error, Result},
+    handler::game::{
+        access::{create_game, join_game, validate_settings},
+        field::create_fields_for_player,
+        result::{archive_players, assign_ranks, close_game, rank_standings, standings},
+    },
+    server::{AppState, Identity},
+};
+use axum::{
+    extract::{Extension, Path},
+    Json,
+};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+pub async fn handle_create_room(
+    identity: Identity,
+    Json(payload): Json<RoomIn>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<RoomOut>> {
+    let mut transaction = state.pool.begin().await?;
+    let user_id = identity.user_id;
+
+    let title = payload.title.trim();
+
+    if title.is_empty() || title.len() > 128 {
+        return Err(Error::BadRequest(
+            "Title must have at least one and at most 128 characters.".to_string(),
+        ));
+    }
+
+    let access_code: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect();
+
+    let room = sqlx::query!(
+        r#"
+            insert into bingo.rooms (title, access_code, created_by)
+            values ($1, $2, $3)
+            returning id
+        "#,
+        title,
+        access_code,
+        user_id,
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"insert into bingo.room_members (room_id, "user_id") values ($1, $2)"#,
+        room.id,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    let result = room_out(room.id, user_id, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(result))
+}
+
+/// Adds the player to the roster and, if a round is running, deals them a card.
+pub async fn handle_join_room(
+    identity: Identity,
+    Path(access_code): Path<String>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<RoomOut>> {
+    let mut transaction = state.pool.begin().await?;
+    let user_id = identity.user_id;
+
+    let room = sqlx::query!(
+        "select id from bingo.rooms where access_code = $1",
+        access_code
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            insert into bingo.room_members (room_id, "user_id") values ($1, $2)
+            on conflict do nothing
+        "#,
+        room.id,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    // the running round is joined like any other game, so it can be locked or full
+
+    let game = sqlx::query!(
+        "select access_code from bingo.games where room_id = $1 and closed = false",
+        room.id
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+
+    if let Some(game) = game {
+        join_game(user_id, game.access_code, None, &mut transaction).await?;
+    }
+
+    let result = room_out(room.id, user_id, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(result))
+}
+
+pub async fn handle_get_room(
+    identity: Identity,
+    Path(room_id): Path<Uuid>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<RoomOut>> {
+    let mut conn = state.pool.acquire().await?;
+
+    let result = room_out(room_id, identity.user_id, &mut conn).await?;
+
+    Ok(Json(result))
+}
+
+/// Removes the player from the roster and from the running round.
+pub async fn handle_leave_room(
+    identity: Identity,
+    Path(room_id): Path<Uuid>,
+    Extension(state): Extension<AppState>,
+) -> Result<()> {
+    let mut transaction = state.pool.begin().await?;
+    let user_id = identity.user_id;
+
+    sqlx::query!(
+        r#"
+            delete from bingo.room_members
+            where room_id = $1 and "user_id" = $2
+            returning room_id
+        "#,
+        room_id,
+        user_id,
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    let game = sqlx::query!(
+        "select id from bingo.games where room_id = $1 and closed = false",
+        room_id
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+
+    if let Some(game) = game {
+        archive_players(game.id, Some(user_id), &mut transaction).await?;
+
+        sqlx::query!(
+            r#"delete from bingo.players where game_id = $1 and "user_id" = $2"#,
+            game.id,
+            user_id,
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        sqlx::query!(
+            r#"delete from bingo.fields where game_id = $1 and "user_id" = $2"#,
+            game.id,
+            user_id,
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Finishes the running round, if any, and starts the next one with everyone on the roster.
+pub async fn handle_start_round(
+    identity: Identity,
+    Path(room_id): Path<Uuid>,
+    Json(payload): Json<GameSettingsIn>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<GameOut>> {
+    let settings = validate_settings(payload)?;
+
+    let mut transaction = state.pool.begin().await?;
+    let user_id = identity.user_id;
+
+    let room = sqlx::query!(
+        r#"
+            select
+                r.id,
+                (select max(g.round) from bingo.games g where g.room_id = r.id) as last_round
+            from bingo.rooms r
+            where r.id = $1 and r.created_by = $2
+            for update
+        "#,
+        room_id,
+        user_id,
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    let running_games = sqlx::query!(
+        "select id from bingo.games where room_id = $1 and closed = false",
+        room.id
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+
+    for game in running_games {
+        close_game(game.id, &mut transaction).await?;
+    }
+
+    let round = room.last_round.unwrap_or(0) + 1;
+    let game = create_game(user_id, &settings, Some((room.id, round)), &mut transaction).await?;
+
+    let member_ids = sqlx::query!(
+        r#"select "user_id" from bingo.room_members where room_id = $1 order by joined_at"#,
+        room.id
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+
+    for member in member_ids {
+        create_fields_for_player(game.id, member.user_id, &mut transaction).await?;
+    }
+
+    let Json(mut result) = join_game(user_id, game.access_code, None, &mut transaction).await?;
+    result.continued = false;
+
+    transaction.commit().await?;
+
+    Ok(Json(result))
+}
+
+/// Sums up the standings of all rounds. A round counts as won once it is finished.
+pub async fn handle_room_scoreboard(
+    identity: Identity,
+    Path(room_id): Path<Uuid>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<Vec<ScoreOut>>> {
+    let mut conn = state.pool.acquire().await?;
+    let user_id = identity.user_id;
+
+    let room = room_out(room_id, user_id, &mut conn).await?;
+
+    let mut scores: Vec<ScoreOut> = Vec::new();
+
+    for round in &room.rounds {
+        let mut standings = standings(round.game_id, round.finished, user_id, &mut conn).await?;
+        rank_standings(&mut standings, round.finished);
+
+        for standing in standings {
+            let score = match scores.iter_mut().find(|v| v.user_id == standing.user_id) {
+                Some(score) => score,
+                None => {
+                    scores.push(ScoreOut {
+                        user_id: standing.user_id,
+                        username: String::new(),
+                        rounds: 0,
+                        wins: 0,
+                        bingos: 0,
+                        hits: 0,
+                        rank: 0,
+                        is_me: standing.is_me,
+                    });
+                    scores.last_mut().unwrap()
+                }
+            };
+
+            // rounds are ordered, so the latest name wins
+            score.username = standing.username;
+            score.rounds += 1;
+            score.wins += standing.winner as i32;
+            score.bingos += standing.bingos;
+            score.hits += standing.hits;
+        }
+    }
+
+    rank_scores(&mut scores);
+
+    Ok(Json(scores))
+}
+
+/// Only members see a room.
+async fn room_out(room_id: Uuid, user_id: Uuid, conn: &mut PgConnection) -> Result<RoomOut> {
+    let room = sqlx::query!(
+        r#"
+            select r.id, r.title, r.access_code, r.created_by
+            from bingo.rooms r
+            where
+                r.id = $1
+                and exists (
+                    select from bingo.room_members m where m.room_id = r.id and m.user_id = $2
+                )
+        "#,
+        room_id,
+        user_id,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let members = sqlx::query!(
+        r#"
+            select "user_id", "username" from bingo.room_members
+            where room_id = $1
+            order by joined_at
+        "#,
+        room.id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|v| RoomMemberOut {
+        user_id: v.user_id,
+        username: v.username,
+        is_me: v.user_id == user_id,
+    })
+    .collect::<Vec<RoomMemberOut>>();
+
+    let rounds = sqlx::query!(
+        r#"
+            select g.id, g.round as "round!", gt.title, g.closed
+            from
+                bingo.games g
+                join bingo.game_templates gt on gt.id = g.game_template_id
+            where g.room_id = $1
+            order by g.round
+        "#,
+        room.id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|v| RoundOut {
+        game_id: v.id,
+        round: v.round,
+        title: v.title,
+        finished: v.closed,
+    })
+    .collect::<Vec<RoundOut>>();
+
+    Ok(RoomOut {
+        id: room.id,
+        title: room.title,
+        access_code: room.access_code,
+        is_host: room.created_by == user_id,
+        members,
+        game_id: rounds.iter().find(|v| !v.finished).map(|v| v.game_id),
+        rounds,
+    })
+}
+
+/// Sorts scores by bingos and hits, tied players share a rank.
+fn rank_scores(scores: &mut [ScoreOut]) {
+    scores.sort_by(|a, b| {
+        b.bingos
+            .cmp(&a.bingos)
+            .then(b.hits.cmp(&a.hits))
+            .then(a.username.cmp(&b.username))
+    });
+
+    assign_ranks(scores, |v| (v.bingos, v.hits), |v, rank| v.rank = rank);
+}
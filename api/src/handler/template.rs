@@ -51,6 +51,7 @@ pub async fn handle_list_templates(
                         bingo.game_templates gt
                         
                         left outer join bingo.games active_game on active_game.game_template_id = gt.id
+                            and active_game.closed = false
                         
                         left outer join bingo.players joinable_game_player on joinable_game_player.user_id = $1
                             and joinable_game_player.game_id = active_game.id
@@ -74,6 +75,8 @@ pub async fn handle_list_templates(
                                 count(ft.game_template_id) field_amount
                             from
                                 bingo.field_templates as ft
+                            where
+                                ft.game_id is null
                             group by
                                 ft.game_template_id
                         ) ft on ft.game_template_id = gt.id
@@ -111,25 +114,29 @@ pub async fn handle_create_template(
     Json(payload): Json<TemplateIn>,
     identity: Identity,
 ) -> Result<String> {
-    let pool = &state.pool;
-
     if payload.title.is_empty() || payload.title.len() > 128 {
         return Err(Error::BadRequest(
             "Title must have at least one and at most 128 characters.".to_string(),
         ));
     }
 
-    let game_template = sqlx::query!(
-        r#"
-            insert into bingo.game_templates (title, "language", created_by)
-            values ($1, $2, $3) returning id
-        "#,
-        payload.title,
-        "ger",
-        identity.user_id
-    )
-    .fetch_one(pool)
-    .await?;
+    // check if rules only refer to existing categories
+
+    if payload.rules.len() > 16 {
+        return Err(Error::BadRequest(
+            "Templates can have at most 16 rules.".to_string(),
+        ));
+    }
+
+    let categories = payload
+        .fields
+        .iter()
+        .filter_map(|v| v.category())
+        .collect::<Vec<&str>>();
+
+    for rule in &payload.rules {
+        rule.validate(&categories)?;
+    }
 
     // check if template has at least nine fields
 
@@ -142,26 +149,60 @@ pub async fn handle_create_template(
     // check if all fields are valid
 
     for field in &payload.fields {
-        if field.is_empty() || field.len() > 128 {
+        if field.caption().is_empty() || field.caption().chars().count() > 128 {
             return Err(Error::BadRequest(
                 "Field captions must have at least one and at most 128 characters.".to_string(),
             ));
         }
+        if !(1..=100).contains(&field.weight()) {
+            return Err(Error::BadRequest(
+                "Field weights must be between 1 and 100.".to_string(),
+            ));
+        }
+        if let Some(category) = field.category() {
+            if category.is_empty() || category.chars().count() > 32 {
+                return Err(Error::BadRequest(
+                    "Field categories must have at least one and at most 32 characters."
+                        .to_string(),
+                ));
+            }
+        }
     }
 
+    // the template is only written once everything is valid, together with its fields
+
+    let mut transaction = state.pool.begin().await?;
+
+    let game_template = sqlx::query!(
+        r#"
+            insert into bingo.game_templates (title, "language", created_by, rules)
+            values ($1, $2, $3, $4) returning id
+        "#,
+        payload.title,
+        "ger",
+        identity.user_id,
+        serde_json::to_value(&payload.rules)?,
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
     for field in &payload.fields {
         sqlx::query!(
             r#"
-                insert into bingo.field_templates (game_template_id, caption)
-                values ($1, $2)
+                insert into bingo.field_templates (game_template_id, caption, weight, category)
+                values ($1, $2, $3, $4)
             "#,
             game_template.id,
-            field
+            field.caption(),
+            field.weight(),
+            field.category(),
         )
-        .execute(pool)
+        .execute(&mut transaction)
         .await?;
     }
 
+    transaction.commit().await?;
+
     Ok("Template successfully creates.".to_string())
 }
 
@@ -215,6 +256,13 @@ pub async fn handle_delete_template(
     .execute(&mut transaction)
     .await?;
 
+    sqlx::query!(
+        "delete from bingo.results where game_id = any($1)",
+        &game_ids
+    )
+    .execute(&mut transaction)
+    .await?;
+
     sqlx::query!(
         "delete from bingo.players where game_id = any($1)",
         &game_ids
@@ -222,6 +270,35 @@ pub async fn handle_delete_template(
     .execute(&mut transaction)
     .await?;
 
+    sqlx::query!("delete from bingo.calls where game_id = any($1)", &game_ids)
+        .execute(&mut transaction)
+        .await?;
+
+    sqlx::query!(
+        "delete from bingo.messages where game_id = any($1)",
+        &game_ids
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    sqlx::query!(
+        "delete from bingo.spectators where game_id = any($1)",
+        &game_ids
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    sqlx::query!("delete from bingo.teams where game_id = any($1)", &game_ids)
+        .execute(&mut transaction)
+        .await?;
+
+    sqlx::query!(
+        "delete from bingo.field_templates where game_id = any($1)",
+        &game_ids
+    )
+    .execute(&mut transaction)
+    .await?;
+
     sqlx::query!("delete from bingo.games where id = any($1)", &game_ids)
         .execute(&mut transaction)
         .await?;
@@ -0,0 +1,3 @@
+pub mod bitboard;
+pub mod error;
+pub mod pattern;
@@ -1,3 +1,4 @@
+use api::error;
 use dotenv::dotenv;
 use sqlx::postgres::PgPool;
 use std::{collections::HashMap, env};
@@ -5,7 +6,8 @@ use tokio::sync::watch;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 mod body;
-mod error;
+mod caller;
+mod deadline;
 mod handler;
 mod pg_listen;
 mod server;
@@ -39,6 +41,12 @@ async fn main() {
         },
         _ = pg_listen::listen(&pool, sender) => {
             tracing::error!("pg_listener::listen shut down");
+        },
+        _ = deadline::close_expired_games(&pool) => {
+            tracing::error!("deadline::close_expired_games shut down");
+        },
+        _ = caller::draw_captions(&pool) => {
+            tracing::error!("caller::draw_captions shut down");
         }
     );
 }
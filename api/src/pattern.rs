@@ -0,0 +1,590 @@
+use crate::{
+    bitboard::{self, complete, covers, Masks},
+    error::{Error, Result},
+};
+use serde::{Deserialize, Serialize};
+
+/// A card's hits as bitboard together with the masks of its grid size.
+pub struct Board {
+    pub bits: u128,
+    pub masks: &'static Masks,
+}
+
+impl Board {
+    /// Returns `None` if the field amount does not match a supported grid size.
+    pub fn new(fields: &[bool], width: usize, height: usize) -> Option<Board> {
+        if fields.len() != width * height {
+            return None;
+        }
+
+        bitboard::masks(width, height).map(|masks| Board {
+            bits: bitboard::from_fields(fields),
+            masks,
+        })
+    }
+}
+
+pub trait WinPattern {
+    /// Returns how many times the pattern is completed on the board.
+    fn bingos(&self, board: &Board) -> i32;
+}
+
+/// Complete rows and columns.
+pub struct Lines;
+
+impl WinPattern for Lines {
+    fn bingos(&self, board: &Board) -> i32 {
+        complete(board.bits, &board.masks.rows) + complete(board.bits, &board.masks.columns)
+    }
+}
+
+/// Complete diagonals, only on square boards.
+pub struct Diagonals;
+
+impl WinPattern for Diagonals {
+    fn bingos(&self, board: &Board) -> i32 {
+        complete(board.bits, &board.masks.diagonals)
+    }
+}
+
+pub struct FourCorners;
+
+impl WinPattern for FourCorners {
+    fn bingos(&self, board: &Board) -> i32 {
+        covers(board.bits, board.masks.corners) as i32
+    }
+}
+
+/// Both diagonals at once, only on square boards.
+pub struct X;
+
+impl WinPattern for X {
+    fn bingos(&self, board: &Board) -> i32 {
+        let diagonals = &board.masks.diagonals;
+
+        (!diagonals.is_empty() && complete(board.bits, diagonals) == diagonals.len() as i32) as i32
+    }
+}
+
+/// Middle row and middle column at once, only on boards with an odd width and height.
+pub struct Plus;
+
+impl WinPattern for Plus {
+    fn bingos(&self, board: &Board) -> i32 {
+        board
+            .masks
+            .plus
+            .map(|mask| covers(board.bits, mask) as i32)
+            .unwrap_or(0)
+    }
+}
+
+/// Every field of the board.
+pub struct Blackout;
+
+impl WinPattern for Blackout {
+    fn bingos(&self, board: &Board) -> i32 {
+        covers(board.bits, board.masks.all) as i32
+    }
+}
+
+/// A shape defined by the host, ordered row by row like the board itself.
+pub struct Shape<'a> {
+    pub mask: &'a [bool],
+}
+
+impl WinPattern for Shape<'_> {
+    fn bingos(&self, board: &Board) -> i32 {
+        let size = board.masks.width * board.masks.height;
+
+        (self.mask.len() == size && covers(board.bits, bitboard::from_fields(self.mask))) as i32
+    }
+}
+
+/// Win patterns a host can choose from when starting a game.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum WinPatternKind {
+    Lines,
+    Diagonals,
+    FourCorners,
+    X,
+    Plus,
+    Blackout,
+    Custom { mask: Vec<bool> },
+}
+
+impl WinPatternKind {
+    /// Rows, columns and diagonals, as in classic bingo.
+    pub fn default_set() -> Vec<WinPatternKind> {
+        vec![WinPatternKind::Lines, WinPatternKind::Diagonals]
+    }
+
+    pub fn validate(&self, width: usize, height: usize) -> Result<()> {
+        match self {
+            WinPatternKind::X if width != height => Err(Error::BadRequest(
+                "The X pattern needs a square board.".to_string(),
+            )),
+            WinPatternKind::Plus if width % 2 == 0 || height % 2 == 0 => Err(Error::BadRequest(
+                "The plus pattern needs an odd grid width and height.".to_string(),
+            )),
+            WinPatternKind::Custom { mask } if mask.len() != width * height => {
+                Err(Error::BadRequest(format!(
+                    "Custom patterns must have one entry per field ({}).",
+                    width * height
+                )))
+            }
+            WinPatternKind::Custom { mask } if !mask.iter().any(|v| *v) => Err(Error::BadRequest(
+                "Custom patterns must contain at least one field.".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl WinPattern for WinPatternKind {
+    fn bingos(&self, board: &Board) -> i32 {
+        match self {
+            WinPatternKind::Lines => Lines.bingos(board),
+            WinPatternKind::Diagonals => Diagonals.bingos(board),
+            WinPatternKind::FourCorners => FourCorners.bingos(board),
+            WinPatternKind::X => X.bingos(board),
+            WinPatternKind::Plus => Plus.bingos(board),
+            WinPatternKind::Blackout => Blackout.bingos(board),
+            WinPatternKind::Custom { mask } => Shape { mask }.bingos(board),
+        }
+    }
+}
+
+/// Sums up how often each of the game's win patterns is completed.
+/// Fields are ordered row by row.
+pub fn bingos(fields: &[bool], width: usize, height: usize, patterns: &[WinPatternKind]) -> i32 {
+    match Board::new(fields, width, height) {
+        Some(board) => patterns.iter().map(|v| v.bingos(&board)).sum(),
+        None => {
+            tracing::error!(
+                "invalid field amount {} for a {}x{} grid",
+                fields.len(),
+                width,
+                height
+            );
+            0
+        }
+    }
+}
+
+#[test]
+fn test_patterns() {
+    let count = |pattern: WinPatternKind, fields: &[bool], width: usize, height: usize| {
+        pattern.bingos(&Board::new(fields, width, height).unwrap())
+    };
+
+    let fields = [
+        true, false, true, //
+        false, true, false, //
+        true, false, true, //
+    ];
+    assert_eq!(count(WinPatternKind::Lines, &fields, 3, 3), 0);
+    assert_eq!(count(WinPatternKind::Diagonals, &fields, 3, 3), 2);
+    assert_eq!(count(WinPatternKind::FourCorners, &fields, 3, 3), 1);
+    assert_eq!(count(WinPatternKind::X, &fields, 3, 3), 1);
+    assert_eq!(count(WinPatternKind::Plus, &fields, 3, 3), 0);
+    assert_eq!(count(WinPatternKind::Blackout, &fields, 3, 3), 0);
+
+    let fields = [
+        false, true, false, //
+        true, true, true, //
+        false, true, false, //
+    ];
+    assert_eq!(count(WinPatternKind::Lines, &fields, 3, 3), 2);
+    assert_eq!(count(WinPatternKind::X, &fields, 3, 3), 0);
+    assert_eq!(count(WinPatternKind::Plus, &fields, 3, 3), 1);
+    assert_eq!(count(WinPatternKind::FourCorners, &fields, 3, 3), 0);
+
+    let fields = [true; 15];
+    assert_eq!(count(WinPatternKind::Lines, &fields, 5, 3), 8);
+    assert_eq!(count(WinPatternKind::Diagonals, &fields, 5, 3), 0);
+    assert_eq!(count(WinPatternKind::X, &fields, 5, 3), 0);
+    assert_eq!(count(WinPatternKind::Plus, &fields, 5, 3), 1);
+    assert_eq!(count(WinPatternKind::FourCorners, &fields, 5, 3), 1);
+    assert_eq!(count(WinPatternKind::Blackout, &fields, 5, 3), 1);
+
+    // custom shape: a small "L"
+
+    let mask = vec![
+        true, false, false, //
+        true, false, false, //
+        true, true, false, //
+    ];
+    let fields = [
+        true, false, true, //
+        true, false, false, //
+        true, true, false, //
+    ];
+    assert_eq!(
+        count(WinPatternKind::Custom { mask: mask.clone() }, &fields, 3, 3),
+        1
+    );
+    let fields = [
+        true, false, false, //
+        false, false, false, //
+        true, true, true, //
+    ];
+    assert_eq!(count(WinPatternKind::Custom { mask }, &fields, 3, 3), 0);
+}
+
+#[test]
+fn test_validate_patterns() {
+    assert!(WinPatternKind::Lines.validate(3, 5).is_ok());
+    assert!(WinPatternKind::X.validate(3, 3).is_ok());
+    assert!(WinPatternKind::X.validate(3, 5).is_err());
+    assert!(WinPatternKind::Plus.validate(5, 3).is_ok());
+    assert!(WinPatternKind::Plus.validate(4, 4).is_err());
+    assert!(WinPatternKind::Custom {
+        mask: vec![true, false, false, true]
+    }
+    .validate(2, 2)
+    .is_ok());
+    assert!(WinPatternKind::Custom {
+        mask: vec![true; 9]
+    }
+    .validate(2, 2)
+    .is_err());
+    assert!(WinPatternKind::Custom {
+        mask: vec![false; 4]
+    }
+    .validate(2, 2)
+    .is_err());
+}
+
+#[test]
+fn test_board_dimensions() {
+    // boards have between two and nine fields per side
+
+    assert!(Board::new(&[], 0, 0).is_none());
+    assert!(Board::new(&[false; 1], 1, 1).is_none());
+    assert!(Board::new(&[false; 2], 1, 2).is_none());
+    assert!(Board::new(&[false; 3], 2, 2).is_none());
+    assert!(Board::new(&[false; 4], 2, 2).is_some());
+    assert!(Board::new(&[false; 5], 2, 2).is_none());
+    assert!(Board::new(&[false; 9], 3, 3).is_some());
+    assert!(Board::new(&[false; 10], 3, 3).is_none());
+    assert!(Board::new(&[false; 15], 3, 5).is_some());
+    assert!(Board::new(&[false; 15], 5, 3).is_some());
+    assert!(Board::new(&[false; 16], 5, 3).is_none());
+    assert!(Board::new(&[false; 81], 9, 9).is_some());
+    assert!(Board::new(&[false; 100], 10, 10).is_none());
+}
+
+#[test]
+fn test_bingos() {
+    let patterns = WinPatternKind::default_set();
+
+    // invalid field amounts
+
+    assert_eq!(bingos(&[], 2, 2, &patterns), 0);
+    assert_eq!(bingos(&[false], 2, 2, &patterns), 0);
+    assert_eq!(bingos(&[false, false], 2, 2, &patterns), 0);
+    assert_eq!(bingos(&[false, false, false], 2, 2, &patterns), 0);
+    assert_eq!(
+        bingos(&[false, false, false, false, false], 3, 3, &patterns),
+        0
+    );
+    assert_eq!(
+        bingos(&[false, false, false, false, false, false], 3, 3, &patterns),
+        0
+    );
+    assert_eq!(
+        bingos(
+            &[false, false, false, false, false, false, false],
+            3,
+            3,
+            &patterns
+        ),
+        0
+    );
+    assert_eq!(
+        bingos(
+            &[false, false, false, false, false, false, false, false],
+            3,
+            3,
+            &patterns
+        ),
+        0
+    );
+
+    // 2x2
+
+    let fields = vec![
+        false, false, //
+        false, false, //
+    ];
+    assert_eq!(bingos(&fields, 2, 2, &patterns), 0);
+
+    let fields = vec![
+        true, true, //
+        false, false, //
+    ];
+    assert_eq!(bingos(&fields, 2, 2, &patterns), 1);
+
+    let fields = vec![
+        false, false, //
+        true, true, //
+    ];
+    assert_eq!(bingos(&fields, 2, 2, &patterns), 1);
+
+    let fields = vec![
+        true, false, //
+        false, true, //
+    ];
+    assert_eq!(bingos(&fields, 2, 2, &patterns), 1);
+
+    let fields = vec![
+        false, true, //
+        true, false, //
+    ];
+    assert_eq!(bingos(&fields, 2, 2, &patterns), 1);
+
+    let fields = vec![
+        true, false, //
+        true, false, //
+    ];
+    assert_eq!(bingos(&fields, 2, 2, &patterns), 1);
+
+    let fields = vec![
+        false, true, //
+        false, true, //
+    ];
+    assert_eq!(bingos(&fields, 2, 2, &patterns), 1);
+
+    let fields = vec![
+        true, true, //
+        true, true, //
+    ];
+    assert_eq!(bingos(&fields, 2, 2, &patterns), 6);
+
+    // 3x3
+    let fields = vec![
+        true, true, true, //
+        true, true, true, //
+        true, true, true, //
+    ];
+    assert_eq!(bingos(&fields, 3, 3, &patterns), 8);
+
+    // 4x4
+    let fields = vec![
+        true, true, true, true, //
+        true, true, true, true, //
+        true, true, true, true, //
+        true, true, true, true, //
+    ];
+    assert_eq!(bingos(&fields, 4, 4, &patterns), 10);
+
+    // 5x5
+
+    let fields = vec![
+        true, true, true, true, true, //
+        true, true, true, true, true, //
+        true, true, true, true, true, //
+        true, true, true, true, true, //
+        true, true, true, true, true, //
+    ];
+    assert_eq!(bingos(&fields, 5, 5, &patterns), 12);
+
+    let fields = vec![
+        true, true, true, true, true, //
+        true, true, true, true, true, //
+        true, true, true, true, true, //
+        true, false, true, true, true, //
+        true, true, true, true, true, //
+    ];
+    assert_eq!(bingos(&fields, 5, 5, &patterns), 9);
+
+    let fields = vec![
+        true, true, true, true, true, //
+        true, true, true, true, true, //
+        true, true, false, true, true, //
+        true, true, true, true, true, //
+        true, true, true, true, true, //
+    ];
+    assert_eq!(bingos(&fields, 5, 5, &patterns), 8);
+
+    // 6 x 6
+
+    let fields = vec![
+        true, true, true, true, true, true, //
+        true, true, true, true, true, true, //
+        true, true, true, true, true, true, //
+        true, true, true, true, true, true, //
+        true, true, true, true, true, true, //
+        true, true, true, true, true, true, //
+    ];
+    assert_eq!(bingos(&fields, 6, 6, &patterns), 14);
+    // field amount not matching the grid
+
+    assert_eq!(bingos(&[true; 9], 2, 2, &patterns), 0);
+    assert_eq!(bingos(&[true; 15], 3, 3, &patterns), 0);
+}
+
+#[test]
+fn test_bingos_rectangular() {
+    let patterns = WinPatternKind::default_set();
+
+    // 3x5: three columns, five rows, no diagonals
+
+    let fields = vec![
+        true, true, true, //
+        true, true, true, //
+        true, true, true, //
+        true, true, true, //
+        true, true, true, //
+    ];
+    assert_eq!(bingos(&fields, 3, 5, &patterns), 8);
+
+    let fields = vec![
+        true, false, false, //
+        false, true, false, //
+        false, false, true, //
+        false, false, false, //
+        false, false, false, //
+    ];
+    assert_eq!(bingos(&fields, 3, 5, &patterns), 0);
+
+    let fields = vec![
+        false, true, false, //
+        false, true, false, //
+        true, true, true, //
+        false, true, false, //
+        false, true, false, //
+    ];
+    assert_eq!(bingos(&fields, 3, 5, &patterns), 2);
+
+    // 5x3: five columns, three rows, no diagonals
+
+    let fields = vec![
+        true, true, true, true, true, //
+        true, true, true, true, true, //
+        true, true, true, true, true, //
+    ];
+    assert_eq!(bingos(&fields, 5, 3, &patterns), 8);
+
+    let fields = vec![
+        true, false, false, false, true, //
+        true, false, true, false, true, //
+        true, true, true, true, true, //
+    ];
+    assert_eq!(bingos(&fields, 5, 3, &patterns), 3);
+
+    let fields = vec![
+        true, false, false, false, false, //
+        false, true, false, false, false, //
+        false, false, true, false, false, //
+    ];
+    assert_eq!(bingos(&fields, 5, 3, &patterns), 0);
+}
+
+/// Straightforward line counting the bitboard scorer is checked against.
+#[cfg(test)]
+fn oracle(fields: &[bool], width: usize, height: usize) -> i32 {
+    let hit = |x: usize, y: usize| fields[y * width + x];
+
+    let rows = (0..height)
+        .filter(|y| (0..width).all(|x| hit(x, *y)))
+        .count();
+    let columns = (0..width)
+        .filter(|x| (0..height).all(|y| hit(*x, y)))
+        .count();
+    let diagonals = if width == height {
+        (0..width).all(|i| hit(i, i)) as usize + (0..width).all(|i| hit(i, width - 1 - i)) as usize
+    } else {
+        0
+    };
+
+    (rows + columns + diagonals) as i32
+}
+
+#[cfg(test)]
+fn board_strategy() -> impl proptest::strategy::Strategy<Value = (usize, usize, Vec<bool>)> {
+    use proptest::prelude::*;
+
+    (2usize..=9, 2usize..=9).prop_flat_map(|(width, height)| {
+        (
+            Just(width),
+            Just(height),
+            prop::collection::vec(prop::bool::weighted(0.8), width * height),
+        )
+    })
+}
+
+/// A board together with a custom shape of the same size.
+#[cfg(test)]
+fn shape_strategy(
+) -> impl proptest::strategy::Strategy<Value = (usize, usize, Vec<bool>, Vec<bool>)> {
+    use proptest::prelude::*;
+
+    board_strategy().prop_flat_map(|(width, height, fields)| {
+        (
+            Just(width),
+            Just(height),
+            Just(fields),
+            prop::collection::vec(prop::bool::weighted(0.3), width * height),
+        )
+    })
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn test_bingos_match_oracle((width, height, fields) in board_strategy()) {
+        let patterns = WinPatternKind::default_set();
+
+        proptest::prop_assert_eq!(
+            bingos(&fields, width, height, &patterns),
+            oracle(&fields, width, height)
+        );
+    }
+
+    #[test]
+    fn test_blackout_and_corners_match_oracle((width, height, fields) in board_strategy()) {
+        let hit = |x: usize, y: usize| fields[y * width + x];
+        let corners = hit(0, 0) && hit(width - 1, 0) && hit(0, height - 1) && hit(width - 1, height - 1);
+
+        proptest::prop_assert_eq!(
+            bingos(&fields, width, height, &[WinPatternKind::Blackout]),
+            fields.iter().all(|v| *v) as i32
+        );
+        proptest::prop_assert_eq!(
+            bingos(&fields, width, height, &[WinPatternKind::FourCorners]),
+            corners as i32
+        );
+    }
+
+    #[test]
+    fn test_x_and_plus_match_oracle((width, height, fields) in board_strategy()) {
+        let hit = |x: usize, y: usize| fields[y * width + x];
+        let x = width == height && (0..width).all(|i| hit(i, i) && hit(i, width - 1 - i));
+        let plus = width % 2 == 1
+            && height % 2 == 1
+            && (0..width).all(|x| hit(x, height / 2))
+            && (0..height).all(|y| hit(width / 2, y));
+
+        proptest::prop_assert_eq!(
+            bingos(&fields, width, height, &[WinPatternKind::X]),
+            x as i32
+        );
+        proptest::prop_assert_eq!(
+            bingos(&fields, width, height, &[WinPatternKind::Plus]),
+            plus as i32
+        );
+    }
+
+    #[test]
+    fn test_shapes_match_oracle((width, height, fields, mask) in shape_strategy()) {
+        let covered = mask.iter().zip(&fields).all(|(part, hit)| !part || *hit);
+
+        proptest::prop_assert_eq!(
+            bingos(&fields, width, height, &[WinPatternKind::Custom { mask }]),
+            covered as i32
+        );
+    }
+}
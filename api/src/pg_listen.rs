@@ -8,32 +8,49 @@ use std::collections::HashMap;
 use tokio::sync::watch::Sender;
 use uuid::Uuid;
 
-pub async fn listen(pool: &PgPool, sender: Sender<HashMap<Uuid, DateTime<Utc>>>) {
+/// What changed in a game. Chat messages do not affect the rest of the game, so sockets
+/// only reload the chat for them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Topic {
+    Game,
+    Chat,
+}
+
+/// Latest change of each game and topic.
+pub type Changes = HashMap<(Uuid, Topic), DateTime<Utc>>;
+
+pub async fn listen(pool: &PgPool, sender: Sender<Changes>) {
     if let Err(err) = process_notifications(pool, sender).await {
         tracing::error!("fn process_notifications failes, err: {:?}", err);
     }
 }
 
-async fn process_notifications(
-    pool: &PgPool,
-    sender: Sender<HashMap<Uuid, DateTime<Utc>>>,
-) -> Result<(), Error> {
+async fn process_notifications(pool: &PgPool, sender: Sender<Changes>) -> Result<(), Error> {
     let mut listener = PgListener::connect_with(pool).await?;
 
     listener
-        .listen_all(vec!["fields_update", "players_update"])
+        .listen_all(vec![
+            "fields_update",
+            "players_update",
+            "games_update",
+            "messages_update",
+        ])
         .await?;
 
     loop {
         let notification = listener.recv().await?;
         let game_update: PgGameUpdateNotification = serde_json::from_str(notification.payload())?;
         let game_id = game_update.game_id;
+        let topic = match notification.channel() {
+            "messages_update" => Topic::Chat,
+            _ => Topic::Game,
+        };
 
         sender.send_modify(|v| {
             if v.len() > 100000 {
                 *v = HashMap::new();
             } else {
-                v.insert(game_id, Utc::now());
+                v.insert((game_id, topic), Utc::now());
             }
         });
     }
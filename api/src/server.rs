@@ -1,18 +1,19 @@
-use crate::{error, handler};
+use crate::{error, handler, pg_listen::Changes};
 use axum::{
     async_trait,
+    body::{Bytes, HttpBody},
     extract::{Extension, FromRequest, RequestParts},
-    routing::{delete, get, patch, post},
-    Router,
+    routing::{delete, get, patch, post, put},
+    BoxError, Router,
 };
 use axum_extra::extract::cookie::CookieJar;
-use chrono::{DateTime, Utc};
 use http::{
-    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, COOKIE, USER_AGENT},
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, COOKIE, ETAG, IF_MATCH, USER_AGENT},
     Method,
 };
+use serde::de::DeserializeOwned;
 use sqlx::postgres::PgPool;
-use std::{collections::HashMap, env, net::SocketAddr};
+use std::{env, net::SocketAddr};
 use tokio::sync::watch::Receiver;
 use tokio::time::{interval, sleep, Duration};
 use tower::ServiceBuilder;
@@ -23,7 +24,7 @@ use tower_http::{
 };
 use uuid::Uuid;
 
-pub async fn serve(pool: PgPool, receiver: Receiver<HashMap<Uuid, DateTime<Utc>>>) {
+pub async fn serve(pool: PgPool, receiver: Receiver<Changes>) {
     let port = dotenv::var("PORT")
         .unwrap_or_else(|_| "1313".into())
         .parse::<u16>()
@@ -35,14 +36,17 @@ pub async fn serve(pool: PgPool, receiver: Receiver<HashMap<Uuid, DateTime<Utc>>
             AUTHORIZATION,
             CONTENT_TYPE,
             COOKIE,
+            IF_MATCH,
             USER_AGENT,
         ])
+        .expose_headers(vec![ETAG])
         .allow_credentials(true)
         .allow_methods(vec![
             Method::GET,
             Method::POST,
             Method::DELETE,
             Method::PATCH,
+            Method::PUT,
             Method::OPTIONS,
         ])
         .allow_origin(Origin::list(vec![env::var("CORS_ALLOWED_ORIGIN")
@@ -79,25 +83,109 @@ pub async fn serve(pool: PgPool, receiver: Receiver<HashMap<Uuid, DateTime<Utc>>
             delete(handler::template::handle_delete_template),
         )
         .route("/game/:id", get(handler::game::websocket::ws))
+        .route(
+            "/game/:id/spectate",
+            get(handler::game::websocket::spectate),
+        )
+        .route(
+            "/games",
+            get(handler::game::access::handle_list_games)
+                .post(handler::game::access::handle_create_game),
+        )
         .route(
             "/game/start/:id/:grid_size",
-            get(handler::game::access::handle_start_game),
+            get(handler::game::access::handle_start_game)
+                .post(handler::game::access::handle_start_game),
         )
         .route(
             "/game/leave/:id",
-            get(handler::game::access::handle_leave_game),
+            get(handler::game::access::handle_leave_template_games),
+        )
+        .route(
+            "/game/:id/leave",
+            post(handler::game::access::handle_leave_game),
         )
         .route(
             "/game/join/:access_code",
             get(handler::game::access::handle_join_game),
         )
+        .route(
+            "/game/spectate/:access_code",
+            get(handler::game::access::handle_spectate_game),
+        )
+        .route(
+            "/game/:id/finish",
+            post(handler::game::result::handle_finish_game),
+        )
+        .route(
+            "/game/:id/results",
+            get(handler::game::result::handle_game_results),
+        )
+        .route(
+            "/games/results",
+            get(handler::game::result::handle_list_past_games),
+        )
+        .route(
+            "/game/:id/fields",
+            put(handler::game::field::handle_set_fields),
+        )
+        .route(
+            "/game/:id/deal",
+            post(handler::game::card::handle_redeal_cards),
+        )
+        .route(
+            "/game/:id/cards/:user_id",
+            get(handler::game::card::handle_verify_card)
+                .post(handler::game::card::handle_regenerate_card),
+        )
+        .route(
+            "/game/:id/calls",
+            post(handler::game::call::handle_announce_caption),
+        )
+        .route(
+            "/game/:id/draw",
+            post(handler::game::call::handle_draw_caption),
+        )
+        .route(
+            "/game/:id/messages",
+            post(handler::game::chat::handle_send_message),
+        )
+        .route(
+            "/game/:id/messages/:message_id",
+            delete(handler::game::chat::handle_delete_message),
+        )
+        .route(
+            "/game/:id/messages/:message_id/reactions",
+            patch(handler::game::chat::handle_toggle_reaction),
+        )
         .route(
             "/game/:id/username",
             patch(handler::game::player::handle_update_username),
         )
+        .route(
+            "/game/:id/team",
+            patch(handler::game::player::handle_update_team),
+        )
+        .route("/rooms", post(handler::room::handle_create_room))
+        .route("/rooms/:id", get(handler::room::handle_get_room))
+        .route(
+            "/rooms/join/:access_code",
+            get(handler::room::handle_join_room),
+        )
+        .route("/rooms/:id/leave", post(handler::room::handle_leave_room))
+        .route("/rooms/:id/rounds", post(handler::room::handle_start_round))
+        .route(
+            "/rooms/:id/scoreboard",
+            get(handler::room::handle_room_scoreboard),
+        )
         .route(
             "/field/:id",
-            patch(handler::game::field::handle_update_field),
+            patch(handler::game::field::handle_update_field)
+                .put(handler::game::field::handle_set_field),
+        )
+        .route(
+            "/field/:id/confirm",
+            post(handler::game::field::handle_confirm_field),
         )
         .layer(middleware_stack)
         .layer(Extension(pool));
@@ -136,7 +224,7 @@ async fn health() -> error::Result<String> {
 #[derive(Clone)]
 pub struct AppState {
     pub pool: sqlx::Pool<sqlx::Postgres>,
-    pub receiver: Receiver<HashMap<Uuid, DateTime<Utc>>>,
+    pub receiver: Receiver<Changes>,
 }
 
 #[derive(Clone)]
@@ -203,3 +291,52 @@ where
         }
     }
 }
+
+/// A JSON body that can be left out, in which case the defaults apply.
+/// Unlike with `Option<Json<T>>`, invalid bodies are rejected instead of being ignored.
+pub struct OptionalJson<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for OptionalJson<T>
+where
+    T: DeserializeOwned + Default,
+    B: HttpBody + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = error::Error;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let body = Bytes::from_request(req)
+            .await
+            .map_err(|_| error::Error::BadRequest("Request body can not be read.".to_string()))?;
+
+        if body.iter().all(u8::is_ascii_whitespace) {
+            return Ok(OptionalJson(T::default()));
+        }
+
+        Ok(OptionalJson(serde_json::from_slice(&body)?))
+    }
+}
+
+#[tokio::test]
+async fn test_optional_json() {
+    use crate::body::GameOptionsIn;
+    use axum::body::Body;
+    use http::Request;
+
+    let extract = |body: &'static str| async move {
+        let mut req = RequestParts::new(Request::new(Body::from(body)));
+        OptionalJson::<GameOptionsIn>::from_request(&mut req)
+            .await
+            .map(|OptionalJson(v)| (v.width, v.height))
+    };
+
+    assert_eq!(extract("").await.unwrap(), (None, None));
+    assert_eq!(extract(" \n").await.unwrap(), (None, None));
+    assert_eq!(extract("{}").await.unwrap(), (None, None));
+    assert_eq!(extract(r#"{"width": 3}"#).await.unwrap(), (Some(3), None));
+    assert!(extract(r#"{"width": "3"}"#).await.is_err());
+    assert!(extract("{").await.is_err());
+    assert!(extract("null").await.is_err());
+}